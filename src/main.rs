@@ -1,160 +1,372 @@
 mod util;
 use util::workers::*;
+use util::options::Options;
+use util::resources::{ResourcePool, Preemption, Protocol, Wakeup};
+use util::deadlock::{Detector, Recovery};
+use util::memory::{MemoryPool, Strategy};
+use util::paging::{FramePool, Replacement};
+use util::devices::{Device, Discipline};
 use std::sync::{Arc, Mutex, Barrier,Condvar};
+use std::sync::atomic::AtomicUsize;
 use std::thread;
 use std::collections::VecDeque;
 use std::sync::mpsc::channel;
 use std::io;
-fn main() {
-    println!("FOR FCFS 1\nFOR SJF 2\nFOR RR 3\nFOR MLQ 4");
-    let mut algo = Arc::new(Algorithm::SJF);
+
+fn read_line() -> String {
     let mut input = String::new();
-    match io::stdin().read_line(&mut input) {
-        Ok(n) => {
-            input.remove(n - 1);
-            match input.parse::<u16>().unwrap() {
-                1 => {
-                   algo = Arc::new(Algorithm::FCFS); 
-                },
-                2 => {
-                   algo = Arc::new(Algorithm::SJF);
-                },
-                3 => {
-                   algo = Arc::new(Algorithm::RR);
-                },
-                4 => {
-                   algo = Arc::new(Algorithm::MLQ);
-                },
-                _ => panic!("WRONG INPUT!!")
-            }
-        }
-        Err(error) => println!("error: {}", error),
+    if let Err(error) = io::stdin().read_line(&mut input) {
+        println!("error: {}", error);
     }
-    let mut input = String::new();
-    let mut resourses = (0, 0, 0);
-    match io::stdin().read_line(&mut input) {
-        Ok(n) => {
-            input.remove(n - 1);
-            let mut i = 0;
-            for num in input.split(" ") {
-                if i == 0 {
-                    resourses.0 = num.parse::<u16>().unwrap();
-                }
-                if i == 1 {
-                    resourses.1 = num.parse::<u16>().unwrap();
+    input.trim_end_matches(&['\r', '\n'][..]).to_string()
+}
+
+/// Turns `script=3,+A,2,+C:2,4,-A,io:disk:3@40,fork:W,2,join` into phases:
+/// a number computes for that many clocks, `+NAME[:COUNT]` acquires,
+/// `-NAME[:COUNT]` releases, `io:DEVICE:TIME[@TRACK]` does I/O,
+/// `fork:TASK` starts a child from another task line and `join` waits
+/// for the children.
+fn parse_script(pool: &ResourcePool, devices: &[Device], steps: &[String]) -> VecDeque<Phase> {
+    steps.iter().map(|step| {
+        if let Some(io) = step.strip_prefix("io:") {
+            let (name, rest) = io.split_once(':').unwrap_or_else(|| panic!("WRONG INPUT! bad I/O step {}", step));
+            let (time, track) = rest.split_once('@').unwrap_or((rest, "0"));
+            let device = devices.iter().position(|d| d.name == name).unwrap_or_else(|| panic!("WRONG INPUT! unknown device {}", name));
+            let time = time.parse::<u16>().unwrap_or_else(|_| panic!("WRONG INPUT! bad I/O step {}", step));
+            let track = track.parse::<u32>().unwrap_or_else(|_| panic!("WRONG INPUT! bad I/O step {}", step));
+            assert!(time > 0, "WRONG INPUT! I/O step {} takes no time", step);
+            Phase::Io{device, time, track}
+        } else if let Some(name) = step.strip_prefix("fork:") {
+            Phase::Fork(name.to_string())
+        } else if step == "join" {
+            Phase::Join
+        } else if let Some(entry) = step.strip_prefix('+') {
+            Phase::Acquire(pool.demand(&[entry.to_string()]))
+        } else if let Some(entry) = step.strip_prefix('-') {
+            Phase::Release(pool.demand(&[entry.to_string()]))
+        } else {
+            Phase::Compute(step.parse::<u16>().unwrap_or_else(|_| panic!("WRONG INPUT! bad script step {}", step)))
+        }
+    }).filter(|phase| *phase != Phase::Compute(0)).collect()
+}
+
+/// Walks `task`'s script once to check it and to raise its max claim to
+/// the most it will ever hold at a time.
+fn check_script(pool: &ResourcePool, task: &mut Task) {
+    let mut holding = task.resourses.clone();
+    holding.resize(pool.names.len(), 0);
+    let mut compute = 0;
+    for phase in task.phases.iter() {
+        match phase {
+            Phase::Compute(n) => compute += n,
+            Phase::Acquire(units) => {
+                for (r, u) in units.iter().enumerate() {
+                    holding[r] += u;
+                    assert!(holding[r] <= pool.total[r], "WRONG INPUT! {} would hold more {} than exist", task.name, pool.names[r]);
+                    task.max_claim[r] = task.max_claim[r].max(holding[r]);
                 }
-                if i == 2 {
-                    resourses.2 = num.parse::<u16>().unwrap();
-                    break;
+            },
+            Phase::Release(units) => {
+                for (r, u) in units.iter().enumerate() {
+                    holding[r] = holding[r].checked_sub(*u).unwrap_or_else(|| panic!("WRONG INPUT! {} releases more {} than it holds", task.name, pool.names[r]));
                 }
-                i += 1;
+            },
+            Phase::Io{..} | Phase::Fork(_) | Phase::Join => {},
+        }
+    }
+    assert!(compute == task.total_time, "WRONG INPUT! {} has time {} but its script computes for {}", task.name, task.total_time, compute);
+}
+
+/// A task as far as dependencies go: name, arrival, length and the names
+/// of the tasks it comes after.
+type Node = (String, u32, u32, Vec<String>);
+
+/// The chain of dependent tasks that takes longest to get through, even
+/// with a core for every task, and how many clocks that is.
+fn critical_path(nodes: &[Node]) -> (u32, Vec<String>) {
+    // finish[i] is the earliest clock node i can be done by; None while it
+    // is being worked out, to catch cycles.
+    fn finish(nodes: &[Node], i: usize, memo: &mut Vec<Option<Option<(u32, usize)>>>) -> u32 {
+        match memo[i] {
+            Some(Some((f, _))) => return f,
+            Some(None) => panic!("WRONG INPUT! dependency cycle through {}", nodes[i].0),
+            None => memo[i] = Some(None),
+        }
+        let (name, arrival, length, after) = &nodes[i];
+        let mut start = (*arrival, usize::MAX);
+        for a in after {
+            let j = nodes.iter().position(|n| n.0 == *a).unwrap_or_else(|| panic!("WRONG INPUT! {} comes after unknown task {}", name, a));
+            let f = finish(nodes, j, memo);
+            if f > start.0 {
+                start = (f, j);
             }
         }
-        Err(error) => println!("error: {}", error),
+        memo[i] = Some(Some((start.0 + length, start.1)));
+        start.0 + length
     }
-    let mut input = String::new();
-    let mut number_of_proc = 0;
-    match io::stdin().read_line(&mut input) {
-        Ok(n) => {
-            input.remove(n - 1);
-            number_of_proc = input.parse::<u16>().unwrap();
+    let mut memo = vec![None; nodes.len()];
+    let mut end = (0, usize::MAX);
+    for i in 0..nodes.len() {
+        let f = finish(nodes, i, &mut memo);
+        if f > end.0 {
+            end = (f, i);
+        }
+    }
+    let mut path = Vec::new();
+    let mut at = end.1;
+    while at != usize::MAX {
+        path.push(nodes[at].0.clone());
+        at = memo[at].unwrap().unwrap().1;
+    }
+    path.reverse();
+    (end.0, path)
+}
 
+fn main() {
+    println!("FOR FCFS 1\nFOR SJF 2\nFOR RR 3\nFOR MLQ 4\nFOR SRTF 5\nFOR PRIORITY 6\nFOR MLFQ 7\nFOR HRRN 8\nFOR LOTTERY 9\nFOR STRIDE 10\nFOR CFS 11\nFOR EDF 12\nFOR RM 13");
+    println!("(options may follow the number, e.g. \"3 quantum=4 cores=2\" or \"6 preemptive=true\")");
+    let line = read_line();
+    let mut tokens = line.split(' ');
+    let choice = tokens.next().unwrap().parse::<u16>().unwrap();
+    let options = Options::parse(tokens);
+    let algo = match choice {
+        1 => Algorithm::FCFS,
+        2 => Algorithm::SJF,
+        3 => Algorithm::RR,
+        4 => Algorithm::MLQ,
+        5 => Algorithm::SRTF,
+        6 => Algorithm::Priority { preemptive: options.get("preemptive", false) },
+        7 => Algorithm::MLFQ,
+        8 => Algorithm::HRRN,
+        9 => Algorithm::Lottery,
+        10 => Algorithm::Stride,
+        11 => Algorithm::CFS,
+        12 => Algorithm::EDF,
+        13 => Algorithm::RM,
+        _ => panic!("WRONG INPUT!!")
+    };
+    // Either plain counts, named A, B, C, ... in order, or NAME=COUNT pairs.
+    let mut resourses = Vec::new();
+    for (i, token) in read_line().split(' ').filter(|t| !t.is_empty()).enumerate() {
+        match token.split_once('=') {
+            Some((name, num)) => resourses.push((name.to_string(), num.parse::<u16>().unwrap())),
+            None => resourses.push((((b'A' + i as u8) as char).to_string(), token.parse::<u16>().unwrap())),
         }
-        Err(error) => println!("error: {}", error),
     }
+    let mut resourses = ResourcePool::new(resourses);
+    resourses.banker = options.get("avoidance", String::from("none")) == "banker";
+    resourses.preemption = options.get("preempted", Preemption::Release);
+    resourses.protocol = options.get("protocol", Protocol::None);
+    resourses.wakeup = options.get("wakeup", Wakeup::FirstFit);
+    if let Some(size) = options.get_opt("memory") {
+        resourses.memory = Some(MemoryPool::new(size, options.get("alloc", Strategy::FirstFit)));
+    }
+    if let Some(frames) = options.get_opt("frames") {
+        resourses.paging = Some(FramePool::new(frames, options.get("replace", Replacement::Lru), options.get("fault", 3)));
+    }
+    // devices=disk:sstf,net gives each device a name and a discipline,
+    // FCFS unless told otherwise.
+    let devices: Vec<Device> = options.get_list::<String>("devices", Vec::new()).into_iter().map(|d| match d.split_once(':') {
+        Some((name, discipline)) => Device::new(name.to_string(), discipline.parse().unwrap_or_else(|e| panic!("WRONG INPUT! {}", e))),
+        None => Device::new(d, Discipline::Fcfs),
+    }).collect();
+    let number_of_proc = read_line().parse::<u16>().unwrap();
     let (tx, rx) = channel();
-    let resourses = Arc::new(Mutex::new(((Resource::A, resourses.0), (Resource::B, resourses.1), (Resource::C, resourses.2))));
-    let ready_queue = Arc::new(Mutex::new(VecDeque::<Task>::new()));
-    let ready_queues = Arc::new(Mutex::new(Vec::<VecDeque<Task>>::new()));
-    let mut x_q = VecDeque::<Task>::new();
-    let mut y_q = VecDeque::<Task>::new();
-    let mut z_q = VecDeque::<Task>::new();
-    for _ in 0..number_of_proc {
-        let mut name = String::new();
-        let mut task_type = TaskType::X;
-        let mut tt = 0;
-        let mut input = String::new();
-        match io::stdin().read_line(&mut input) {
-            Ok(n) => {
-                    input.remove(n - 1);
-                    let mut i = 0;
-                    for data in input.split(" ") {
-                        if i == 0 {
-                           name.push_str(data.as_ref());
-                        }
-                        if i == 1 {
-                            if data.eq("X") {
-                                task_type = TaskType::X;
-                            } else if data.eq("Y") {
-                                task_type = TaskType::Y;
-                            } else if data.eq("Z") {
-                                task_type = TaskType::Z;
-                            } else {
-                                panic!("WRONG INPUT!");
-                            }
-                        }
-                        if i == 2 {
-                            tt = data.parse::<u16>().unwrap();
-                            break;
-                        }
-                        i += 1;
-                    }
-
-            }
-            Err(error) => println!("error: {}", error),
+    let mut scheduler = algo.scheduler(&options);
+    let mut periodic = Vec::new();
+    let mut pending = Vec::new();
+    let mut nodes: Vec<Node> = Vec::new();
+    let mut tasks = Vec::new();
+    for id in 0..number_of_proc as usize {
+        // name [X|Y|Z] time key=value...
+        let line = read_line();
+        let mut tokens = line.split(' ');
+        let name = tokens.next().unwrap().to_string();
+        let mut field = tokens.next().expect("WRONG INPUT!");
+        let task_type = match field {
+            "X" => Some(TaskType::X),
+            "Y" => Some(TaskType::Y),
+            "Z" => Some(TaskType::Z),
+            _ => None
+        };
+        if task_type.is_some() {
+            field = tokens.next().expect("WRONG INPUT!");
         }
-        let lock = &*ready_queue;
-        let mut q = lock.lock().unwrap();
-        match *algo {
-            Algorithm::MLQ => {
-               match task_type {
-                    TaskType::X => {
-                        x_q.push_back(Task::new(name, task_type, tt));
-                    },
-                    TaskType::Y => {
-                        y_q.push_back(Task::new(name, task_type, tt));
-                    },
-                    TaskType::Z => {
-                        z_q.push_back(Task::new(name, task_type, tt));
-                    }
-               } 
-            },
-            _ => {
-                (*q).push_back(Task::new(name, task_type, tt));
-                match *algo {
-                    Algorithm::SJF => {
-                        (*q).make_contiguous().sort_by(|a, b| a.total_time.cmp(&b.total_time)); 
-                        println!("{:?}", q);
-                    },
-                    _ => {}
-                }
-            }
+        let tt = field.parse::<u16>().expect("WRONG INPUT!");
+        let task_options = Options::parse(tokens);
+        let mut task = Task::new(name, task_type, tt);
+        task.id = id;
+        task.priority = task_options.get("priority", 0);
+        task.base_priority = task.priority;
+        task.tickets = task_options.get("tickets", task.tickets);
+        task.nice = task_options.get::<i8>("nice", 0).clamp(-20, 19);
+        task.deadline = task_options.get_opt("deadline");
+        let needs: Vec<String> = task_options.get_list("need", Vec::new());
+        if !needs.is_empty() {
+            task.resourses = resourses.demand(&needs);
+        }
+        // Labelled tasks come with a demand for A, B and C; only the units
+        // actually asked for have to exist in the pool.
+        let pool = resourses.names.len();
+        assert!(task.resourses.iter().skip(pool).all(|n| *n == 0), "WRONG INPUT! {} needs a resource that doesn't exist", task.name);
+        task.resourses.truncate(pool);
+        let max: Vec<String> = task_options.get_list("max", Vec::new());
+        task.max_claim = resourses.demand(&max);
+        for (r, n) in task.resourses.iter().enumerate() {
+            task.max_claim[r] = task.max_claim[r].max(*n);
         }
+        let script: Vec<String> = task_options.get_list("script", Vec::new());
+        if !script.is_empty() {
+            task.phases = parse_script(&resourses, &devices, &script);
+            check_script(&resourses, &mut task);
+        }
+        task.checkpoint = task.snapshot();
+        task.memory = task_options.get("mem", 0);
+        if task.memory > 0 {
+            let fits = resourses.memory.as_ref().is_some_and(|m| m.can_hold(task.memory));
+            assert!(fits, "WRONG INPUT! {} needs {} units of memory but the pool can't hold them", task.name, task.memory);
+        }
+        task.pages = task_options.get_list("pages", Vec::new());
+        if !task.pages.is_empty() {
+            assert!(resourses.paging.is_some(), "WRONG INPUT! {} has pages but there are no frames", task.name);
+            let mut distinct = task.pages.clone();
+            distinct.sort();
+            distinct.dedup();
+            task.resident = task_options.get("resident", distinct.len());
+            assert!(task.resident > 0, "WRONG INPUT! {} needs a resident set of at least one frame", task.name);
+        }
+        task.period = task_options.get_opt("period");
+        task.phase = task_options.get("phase", 0);
+        task.arrival = task_options.get("arrival", 0);
+        task.after = task_options.get_list("after", Vec::new());
+        tasks.push(task);
+    }
+    // Task lines named in a fork only ever run as children.
+    let forked: Vec<String> = tasks.iter().flat_map(|t| t.phases.iter()).filter_map(|phase| match phase {
+        Phase::Fork(name) => Some(name.clone()),
+        _ => None,
+    }).collect();
+    for name in forked.iter() {
+        assert!(tasks.iter().any(|t| t.name == *name), "WRONG INPUT! fork of unknown task {}", name);
     }
-    {
-        let lock = &*ready_queues;
-        let mut q = lock.lock().unwrap();
-        (*q).push(x_q);
-        (*q).push(y_q);
-        (*q).push(z_q);
-    }
-    let waiting_queue = Arc::new(Mutex::new(VecDeque::<Task>::new()));
-    let cores = vec![Arc::new((Mutex::new(false), Condvar::new())), Arc::new((Mutex::new(false), Condvar::new())), Arc::new((Mutex::new(false), Condvar::new())), Arc::new((Mutex::new(false), Condvar::new()))];
-    let barrier = Arc::new(Barrier::new(5)); 
-    for i in 0..4 {
+    let mut templates = Vec::new();
+    for task in tasks {
+        // Late arrivals, periodic jobs and children register mid-run;
+        // their claims are checked now rather than then.
+        resourses.check_claim(&task);
+        if forked.contains(&task.name) {
+            assert!(task.period.is_none() && task.arrival == 0 && task.after.is_empty(), "WRONG INPUT! forked task {} can't be periodic, arrive later or come after others", task.name);
+            resourses.raise_ceilings(&task);
+            templates.push(task);
+            continue;
+        }
+        if task.period.is_some() {
+            assert!(task.after.is_empty(), "WRONG INPUT! periodic task {} can't come after other tasks", task.name);
+            resourses.raise_ceilings(&task);
+            periodic.push(task);
+            continue;
+        }
+        assert!(nodes.iter().all(|n| n.0 != task.name), "WRONG INPUT! two tasks are called {}", task.name);
+        nodes.push((task.name.clone(), task.arrival, task.length(), task.after.clone()));
+        if task.arrival > 0 || !task.after.is_empty() {
+            resourses.raise_ceilings(&task);
+            pending.push(task);
+        } else {
+            resourses.register(&task);
+            scheduler.admit(task);
+        }
+    }
+    let shared = Shared {
+        scheduler: Arc::new(Mutex::new(scheduler)),
+        resourses: Arc::new(Mutex::new(resourses)),
+        waiting_queue: Arc::new(Mutex::new(VecDeque::<Task>::new())),
+        preempted: Arc::new(Mutex::new(Vec::new())),
+        finished: Arc::new(Mutex::new(Vec::new())),
+        context_switches: Arc::new(AtomicUsize::new(0)),
+        devices: Arc::new(Mutex::new(devices)),
+        aborted: Arc::new(Mutex::new(Vec::new())),
+        templates: Arc::new(templates),
+        spawned: Arc::new(Mutex::new(Vec::new())),
+        next_id: Arc::new(AtomicUsize::new(number_of_proc as usize)),
+    };
+    let number_of_cores = options.get("cores", 4);
+    assert!(number_of_cores > 0, "WRONG INPUT! need at least one core");
+    let cores: Vec<_> = (0..number_of_cores).map(|_| Arc::new((Mutex::new(false), Condvar::new()))).collect();
+    let barrier = Arc::new(Barrier::new(number_of_cores + 1));
+    for (num, core) in cores.iter().enumerate() {
         let barrier = barrier.clone();
         let tx = tx.clone();
-        let core_clone = cores[i].clone();
-        let q = Arc::clone(&ready_queue);
-        let qs = Arc::clone(&ready_queues);
-        let w = Arc::clone(&waiting_queue);
-        let r = Arc::clone(&resourses);
-        let a = Arc::clone(&algo);
-        let num = i;
+        let core_clone = core.clone();
+        let shared = shared.clone();
         thread::spawn(move|| {
-            cpu_worker(tx, format!("core{}", num), core_clone, q, w, r, a, barrier, qs);
+            cpu_worker(tx, format!("core{}", num), core_clone, shared, barrier);
         });
     }
-    master_worker(rx, cores.clone(), resourses.clone(), ready_queue.clone(), waiting_queue.clone(), algo.clone(), barrier.clone(), ready_queues.clone());
+    let workload = Workload {
+        pending,
+        horizon: options.get("horizon", Workload::default_horizon(&periodic)),
+        periodic,
+        critical_path: Some(critical_path(&nodes)).filter(|_| nodes.iter().any(|n| !n.3.is_empty())),
+    };
+    let detector = Detector {
+        period: options.get("detect", 10),
+        recovery: options.get("recovery", Recovery::AbortYoungest),
+    };
+    master_worker(rx, cores.clone(), shared, workload, detector, barrier);
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(name: &str, arrival: u32, length: u32, after: &[&str]) -> Node {
+        (name.to_string(), arrival, length, after.iter().map(|a| a.to_string()).collect())
+    }
+
+    #[test]
+    fn longest_chain_wins() {
+        let nodes = vec![node("A", 0, 3, &[]), node("B", 0, 1, &[]), node("C", 0, 2, &["A", "B"]), node("D", 0, 3, &["B"])];
+        assert_eq!(critical_path(&nodes), (5, vec![String::from("A"), String::from("C")]));
+    }
+
+    #[test]
+    fn late_arrival_starts_chain() {
+        let nodes = vec![node("A", 0, 2, &[]), node("B", 5, 1, &["A"])];
+        assert_eq!(critical_path(&nodes), (6, vec![String::from("B")]));
+    }
+
+    #[test]
+    #[should_panic(expected = "dependency cycle")]
+    fn cycle_is_rejected() {
+        critical_path(&[node("A", 0, 1, &["B"]), node("B", 0, 1, &["A"])]);
+    }
+
+    #[test]
+    #[should_panic(expected = "unknown task")]
+    fn unknown_dependency_is_rejected() {
+        critical_path(&[node("A", 0, 1, &["Z"])]);
+    }
+
+    fn scripted(pool: &ResourcePool, need: &[&str], script: &[&str]) -> Task {
+        let mut task = Task::new(String::from("T"), None, 2);
+        task.resourses = pool.demand(&need.iter().map(|n| n.to_string()).collect::<Vec<_>>());
+        task.max_claim = task.resourses.clone();
+        task.phases = parse_script(pool, &[], &script.iter().map(|s| s.to_string()).collect::<Vec<_>>());
+        task
+    }
+
+    #[test]
+    fn script_raises_max_claim() {
+        let pool = ResourcePool::new(vec![(String::from("A"), 2)]);
+        let mut task = scripted(&pool, &["A:1"], &["1", "+A", "1"]);
+        check_script(&pool, &mut task);
+        assert_eq!(task.max_claim, vec![2]);
+    }
+
+    #[test]
+    #[should_panic(expected = "would hold more A than exist")]
+    fn script_holding_more_than_exists_is_rejected() {
+        let pool = ResourcePool::new(vec![(String::from("A"), 1)]);
+        check_script(&pool, &mut scripted(&pool, &["A:1"], &["1", "+A", "1"]));
+    }
+}
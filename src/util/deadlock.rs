@@ -0,0 +1,312 @@
+use std::collections::VecDeque;
+use std::str::FromStr;
+use super::resources::*;
+use super::workers::{Phase, Task};
+
+/// How the detector breaks a deadlock once it finds one.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Recovery {
+    /// Abort the deadlocked task that entered the system last.
+    AbortYoungest,
+    /// Take every resource away from the least important deadlocked task;
+    /// it stays in the waiting queue and has to acquire them again.
+    PreemptLowestPriority,
+    /// Like `PreemptLowestPriority`, but the victim also loses the work it
+    /// did since its last checkpoint.
+    Rollback,
+}
+
+impl FromStr for Recovery {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Recovery, String> {
+        match s {
+            "abort" => Ok(Recovery::AbortYoungest),
+            "preempt" => Ok(Recovery::PreemptLowestPriority),
+            "rollback" => Ok(Recovery::Rollback),
+            _ => Err(format!("unknown recovery {}", s)),
+        }
+    }
+}
+
+/// Periodically looks for tasks in the waiting queue that can never run
+/// again and gets the system moving by sacrificing one of them at a time.
+#[derive(Debug, Copy, Clone)]
+pub struct Detector {
+    /// Clocks between checks; 0 turns the detector off.
+    pub period: u32,
+    pub recovery: Recovery,
+}
+
+/// Children of `waiting[w]` it is joining that are among `stuck`.
+fn joining(waiting: &VecDeque<Task>, w: usize, stuck: &[usize]) -> Vec<usize> {
+    if waiting[w].phases.front() != Some(&Phase::Join) {
+        return Vec::new();
+    }
+    stuck.iter().copied().filter(|s| waiting[w].children.iter().any(|(c, _)| *c == waiting[*s].id)).collect()
+}
+
+/// Indices into `waiting` of the tasks that stay blocked even if every
+/// other task in the system ran to completion and gave its resources back.
+/// This is the multi-unit detection algorithm, so it is exact where a bare
+/// cycle in the wait-for graph is not. A task joining children can only go
+/// on once none of them is blocked.
+pub fn deadlocked(pool: &ResourcePool, waiting: &VecDeque<Task>) -> Vec<usize> {
+    let blocked: Vec<usize> = waiting.iter().map(|t| t.id).collect();
+    let mut work = pool.available.clone();
+    for (id, claim) in pool.claims.iter() {
+        if !blocked.contains(id) {
+            for (w, h) in work.iter_mut().zip(claim.held.iter()) {
+                *w += *h;
+            }
+        }
+    }
+    let mut stuck: Vec<usize> = (0..waiting.len()).collect();
+    loop {
+        let runnable = stuck.iter().position(|i| waiting[*i].request().iter().enumerate().all(|(r, n)| *n <= work[r]) && joining(waiting, *i, &stuck).is_empty());
+        match runnable {
+            Some(p) => {
+                let i = stuck.remove(p);
+                if let Some(claim) = pool.claims.get(&waiting[i].id) {
+                    for (w, h) in work.iter_mut().zip(claim.held.iter()) {
+                        *w += *h;
+                    }
+                }
+            },
+            None => return stuck,
+        }
+    }
+}
+
+/// Wait-for edges among `stuck`: (waiter, holder, resource) whenever the
+/// waiter is short of a resource the holder has units of, and (parent,
+/// child, None) for a parent joining a child.
+fn wait_for(pool: &ResourcePool, waiting: &VecDeque<Task>, stuck: &[usize]) -> Vec<(usize, usize, Option<ResourceId>)> {
+    let mut edges = Vec::new();
+    for w in stuck {
+        for c in joining(waiting, *w, stuck) {
+            edges.push((*w, c, None));
+        }
+        for (r, n) in waiting[*w].request().iter().enumerate() {
+            if *n <= pool.available[r] {
+                continue;
+            }
+            for h in stuck {
+                let held = pool.claims.get(&waiting[*h].id).map_or(0, |c| c.held[r]);
+                if h != w && held > 0 {
+                    edges.push((*w, *h, Some(r)));
+                }
+            }
+        }
+    }
+    edges
+}
+
+/// One cycle through every group of tasks waiting on each other, written
+/// as `T1 -(A)-> T2 -(C)-> T1`, or `P -(join)-> C` for a join.
+pub fn cycles(pool: &ResourcePool, waiting: &VecDeque<Task>, stuck: &[usize]) -> Vec<String> {
+    let edges = wait_for(pool, waiting, stuck);
+    let mut seen: Vec<usize> = Vec::new();
+    let mut found = Vec::new();
+    for start in stuck {
+        if seen.contains(start) {
+            continue;
+        }
+        // Follow the first edge out of each task until the walk comes back
+        // on itself; via[k] is the resource on the edge path[k] -> path[k + 1].
+        let mut path = vec![*start];
+        let mut via = Vec::new();
+        loop {
+            let at = *path.last().unwrap();
+            let (to, r) = match edges.iter().find(|(w, _, _)| *w == at) {
+                Some((_, to, r)) => (*to, *r),
+                None => break,
+            };
+            via.push(r);
+            if let Some(p) = path.iter().position(|t| *t == to) {
+                if path[p..].iter().all(|t| !seen.contains(t)) {
+                    let mut text = waiting[path[p]].name.clone();
+                    for (k, r) in via.iter().enumerate().skip(p) {
+                        let next = path.get(k + 1).copied().unwrap_or(path[p]);
+                        let on = r.map_or("join", |r| pool.names[r].as_str());
+                        text.push_str(&format!(" -({})-> {}", on, waiting[next].name));
+                    }
+                    found.push(text);
+                }
+                break;
+            }
+            if seen.contains(&to) {
+                break;
+            }
+            path.push(to);
+        }
+        seen.extend(path);
+    }
+    found
+}
+
+impl Detector {
+    /// Reports and breaks every deadlock among the waiting tasks. Tasks that
+    /// ask for more than exists are always aborted; aborted tasks end up in
+    /// `aborted`.
+    pub fn run(&self, pool: &mut ResourcePool, waiting: &mut VecDeque<Task>, aborted: &mut Vec<Task>) {
+        loop {
+            let stuck = deadlocked(pool, waiting);
+            if stuck.is_empty() {
+                return;
+            }
+            let impossible = stuck.iter().copied().find(|i| waiting[*i].resourses.iter().enumerate().any(|(r, n)| *n > pool.total[r]));
+            if let Some(i) = impossible {
+                let task = &waiting[i];
+                println!("{} can never run: needs {} but only {} exist", task.name, pool.describe(&task.resourses), pool.describe(&pool.total));
+                self.abort(pool, waiting, i, aborted);
+                continue;
+            }
+            println!("DEADLOCK among {}", stuck.iter().map(|i| waiting[*i].name.as_str()).collect::<Vec<_>>().join(", "));
+            for cycle in cycles(pool, waiting, &stuck) {
+                println!("\tcycle: {}", cycle);
+            }
+            let holds = |i: &usize| pool.claims.get(&waiting[*i].id).is_some_and(|c| c.held.iter().any(|h| *h > 0));
+            let victim = match self.recovery {
+                Recovery::AbortYoungest => None,
+                _ => stuck.iter().copied().filter(holds).max_by_key(|i| (waiting[*i].priority, waiting[*i].id)),
+            };
+            match victim {
+                Some(i) => {
+                    // The victim queues up behind the tasks it was blocking
+                    // so they get the units it gave back first.
+                    let mut task = waiting.remove(i).unwrap();
+                    let held = release_all(pool, &mut task);
+                    if self.recovery == Recovery::Rollback {
+                        println!("\trecovery: rolled {} back from {} to {} executed, releasing {}", task.name, task.time_executed, task.checkpoint.time_executed, pool.describe(&held));
+                        task.rollback();
+                    } else {
+                        println!("\trecovery: preempted {} from {}", pool.describe(&held), task.name);
+                    }
+                    waiting.push_back(task);
+                },
+                None => {
+                    let i = stuck.iter().copied().max_by_key(|i| (waiting[*i].arrival, waiting[*i].id)).unwrap();
+                    self.abort(pool, waiting, i, aborted);
+                },
+            }
+        }
+    }
+
+    fn abort(&self, pool: &mut ResourcePool, waiting: &mut VecDeque<Task>, i: usize, aborted: &mut Vec<Task>) {
+        let mut task = waiting.remove(i).unwrap();
+        release_all(pool, &mut task);
+        pool.retire(task.id);
+        println!("\trecovery: aborted {}", task.name);
+        aborted.push(task);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pool() -> ResourcePool {
+        ResourcePool::new(vec![(String::from("A"), 1), (String::from("B"), 1)])
+    }
+
+    /// A task in the system holding `held` and blocked asking for `request`
+    /// on top of it.
+    fn waiter(pool: &mut ResourcePool, id: usize, name: &str, held: Vec<u16>, request: Vec<u16>) -> Task {
+        let mut task = Task::new(name.to_string(), None, 1);
+        task.id = id;
+        task.resourses = held.iter().zip(request.iter()).map(|(h, r)| h + r).collect();
+        pool.register(&task);
+        acquire(pool, &mut task, &held);
+        task
+    }
+
+    /// T0 holds A and wants B, T1 holds B and wants A.
+    fn crossed(pool: &mut ResourcePool) -> VecDeque<Task> {
+        let t0 = waiter(pool, 0, "T0", vec![1, 0], vec![0, 1]);
+        let t1 = waiter(pool, 1, "T1", vec![0, 1], vec![1, 0]);
+        VecDeque::from(vec![t0, t1])
+    }
+
+    #[test]
+    fn crossed_holds_deadlock() {
+        let mut pool = pool();
+        let waiting = crossed(&mut pool);
+        let stuck = deadlocked(&pool, &waiting);
+        assert_eq!(stuck, vec![0, 1]);
+        assert_eq!(cycles(&pool, &waiting, &stuck), vec![String::from("T0 -(B)-> T1 -(A)-> T0")]);
+    }
+
+    #[test]
+    fn holder_off_the_queue_is_no_deadlock() {
+        let mut pool = pool();
+        let mut waiting = crossed(&mut pool);
+        waiting.pop_back();
+        assert!(deadlocked(&pool, &waiting).is_empty());
+    }
+
+    #[test]
+    fn abort_breaks_the_cycle() {
+        let mut pool = pool();
+        let mut waiting = crossed(&mut pool);
+        let mut aborted = Vec::new();
+        Detector{period: 1, recovery: Recovery::AbortYoungest}.run(&mut pool, &mut waiting, &mut aborted);
+        assert_eq!(aborted.iter().map(|t| t.name.as_str()).collect::<Vec<_>>(), vec!["T1"]);
+        assert_eq!(waiting.len(), 1);
+        assert!(deadlocked(&pool, &waiting).is_empty());
+    }
+
+    #[test]
+    fn abort_goes_by_arrival_not_input_order() {
+        let mut pool = pool();
+        let mut waiting = crossed(&mut pool);
+        waiting[0].arrival = 2;
+        let mut aborted = Vec::new();
+        Detector{period: 1, recovery: Recovery::AbortYoungest}.run(&mut pool, &mut waiting, &mut aborted);
+        assert_eq!(aborted.iter().map(|t| t.name.as_str()).collect::<Vec<_>>(), vec!["T0"]);
+    }
+
+    #[test]
+    fn preempt_takes_units_from_one_task() {
+        let mut pool = pool();
+        let mut waiting = crossed(&mut pool);
+        let mut aborted = Vec::new();
+        Detector{period: 1, recovery: Recovery::PreemptLowestPriority}.run(&mut pool, &mut waiting, &mut aborted);
+        assert!(aborted.is_empty());
+        assert_eq!(waiting.len(), 2);
+        assert_eq!(pool.available, vec![0, 1]);
+    }
+
+    #[test]
+    fn impossible_request_is_aborted() {
+        let mut pool = pool();
+        let mut waiting = VecDeque::from(vec![waiter(&mut pool, 0, "T", vec![0, 0], vec![2, 0])]);
+        let mut aborted = Vec::new();
+        Detector{period: 1, recovery: Recovery::Rollback}.run(&mut pool, &mut waiting, &mut aborted);
+        assert!(waiting.is_empty());
+        assert_eq!(aborted.len(), 1);
+    }
+
+    #[test]
+    fn join_on_blocked_child_deadlocks() {
+        let mut pool = pool();
+        let mut parent = waiter(&mut pool, 0, "P", vec![1, 0], vec![0, 0]);
+        parent.phases.push_back(Phase::Join);
+        parent.children.push((1, String::from("C")));
+        let child = waiter(&mut pool, 1, "C", vec![0, 0], vec![1, 0]);
+        let waiting = VecDeque::from(vec![parent, child]);
+        let stuck = deadlocked(&pool, &waiting);
+        assert_eq!(stuck, vec![0, 1]);
+        assert_eq!(cycles(&pool, &waiting, &stuck), vec![String::from("P -(join)-> C -(A)-> P")]);
+    }
+
+    #[test]
+    fn held_units_count_toward_an_impossible_need() {
+        let mut pool = pool();
+        let mut waiting = VecDeque::from(vec![waiter(&mut pool, 0, "T", vec![1, 0], vec![1, 0])]);
+        let mut aborted = Vec::new();
+        Detector{period: 1, recovery: Recovery::Rollback}.run(&mut pool, &mut waiting, &mut aborted);
+        assert!(waiting.is_empty());
+        assert_eq!(aborted.len(), 1);
+    }
+}
@@ -0,0 +1,108 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::str::FromStr;
+use super::workers::{Phase, Task};
+
+/// Order in which a device serves the requests queued on it.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Discipline {
+    Fcfs,
+    /// Shortest seek time first: the request whose track is closest to
+    /// where the head is, ties going to the one queued first.
+    Sstf,
+}
+
+impl FromStr for Discipline {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Discipline, String> {
+        match s {
+            "fcfs" => Ok(Discipline::Fcfs),
+            "sstf" => Ok(Discipline::Sstf),
+            _ => Err(format!("unknown device discipline {}", s)),
+        }
+    }
+}
+
+/// An I/O device serving one task at a time. Tasks sit in its queue, off
+/// every core, from the moment they start an I/O burst until it is done.
+#[derive(Debug)]
+pub struct Device {
+    pub name: String,
+    pub discipline: Discipline,
+    pub queue: VecDeque<Task>,
+    pub serving: Option<Task>,
+    remaining: u16,
+    pub head: u32,
+    /// Clocks spent serving a request.
+    pub busy: u32,
+    pub served: u32,
+    /// Tracks the head has moved over in total.
+    pub moved: u64,
+}
+
+impl Device {
+    pub fn new(name: String, discipline: Discipline) -> Device {
+        Device{name, discipline, queue: VecDeque::new(), serving: None, remaining: 0, head: 0, busy: 0, served: 0, moved: 0}
+    }
+
+    /// Queues `task`, whose next phase is an I/O burst on this device.
+    pub fn submit(&mut self, task: Task) {
+        self.queue.push_back(task);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.serving.is_none() && self.queue.is_empty()
+    }
+
+    /// Serves one clock and returns the task whose burst that finished, if
+    /// any, with the burst taken off its script. A request queued during
+    /// the clock starts being served in the next one.
+    pub fn tick(&mut self) -> Option<Task> {
+        let mut done = None;
+        if self.serving.is_some() {
+            self.busy += 1;
+            self.remaining -= 1;
+            if self.remaining == 0 {
+                let mut task = self.serving.take().unwrap();
+                task.phases.pop_front();
+                self.served += 1;
+                done = Some(task);
+            }
+        }
+        if self.serving.is_none() {
+            let next = match self.discipline {
+                Discipline::Fcfs => if self.queue.is_empty() { None } else { Some(0) },
+                Discipline::Sstf => (0..self.queue.len()).min_by_key(|i| track(&self.queue[*i]).abs_diff(self.head)),
+            };
+            if let Some(task) = next.and_then(|i| self.queue.remove(i)) {
+                if let Some(Phase::Io{time, track, ..}) = task.phases.front() {
+                    self.remaining = *time;
+                    self.moved += u64::from(track.abs_diff(self.head));
+                    self.head = *track;
+                }
+                self.serving = Some(task);
+            }
+        }
+        done
+    }
+}
+
+fn track(task: &Task) -> u32 {
+    match task.phases.front() {
+        Some(Phase::Io{track, ..}) => *track,
+        _ => 0,
+    }
+}
+
+impl fmt::Display for Device {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} : ", self.name)?;
+        match &self.serving {
+            Some(task) => write!(f, "serving {} ({} left)", task.name, self.remaining)?,
+            None => write!(f, "free")?,
+        }
+        let queue: Vec<&str> = self.queue.iter().map(|t| t.name.as_str()).collect();
+        write!(f, ", head at {}, queue [{}]", self.head, queue.join(", "))
+    }
+}
@@ -0,0 +1,267 @@
+use std::fmt;
+use std::str::FromStr;
+use super::workers::Task;
+
+/// How a `MemoryPool` picks the hole a request goes into.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Strategy {
+    /// Lowest address that fits.
+    FirstFit,
+    /// Smallest hole that fits.
+    BestFit,
+    /// Largest hole.
+    WorstFit,
+    /// Power-of-two blocks split in halves and merged back with their buddy.
+    Buddy,
+}
+
+impl FromStr for Strategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Strategy, String> {
+        match s {
+            "first" => Ok(Strategy::FirstFit),
+            "best" => Ok(Strategy::BestFit),
+            "worst" => Ok(Strategy::WorstFit),
+            "buddy" => Ok(Strategy::Buddy),
+            _ => Err(format!("unknown allocation strategy {}", s)),
+        }
+    }
+}
+
+/// A run of contiguous memory, either a hole or given to one task.
+#[derive(Debug, Clone)]
+pub struct Block {
+    pub start: u32,
+    pub size: u32,
+    /// Id of the task the block belongs to, `None` for a hole.
+    pub owner: Option<usize>,
+    pub name: String,
+    /// What the owner asked for; less than `size` when buddy rounded it up.
+    pub requested: u32,
+}
+
+/// A contiguous range of memory handed out to tasks for their whole run.
+#[derive(Debug, Clone)]
+pub struct MemoryPool {
+    pub size: u32,
+    pub strategy: Strategy,
+    /// Blocks in address order, covering the whole pool.
+    pub blocks: Vec<Block>,
+    pub allocations: u32,
+    /// Times a task had to wait because no hole fit.
+    pub failures: u32,
+    pub peak_external: f64,
+    pub peak_internal: u32,
+}
+
+impl MemoryPool {
+    pub fn new(size: u32, strategy: Strategy) -> MemoryPool {
+        assert!(size > 0, "WRONG INPUT! memory needs at least one unit");
+        if strategy == Strategy::Buddy {
+            assert!(size.is_power_of_two(), "WRONG INPUT! buddy allocation needs a power of two memory size, got {}", size);
+        }
+        let blocks = vec![Block{start: 0, size, owner: None, name: String::new(), requested: 0}];
+        MemoryPool{size, strategy, blocks, allocations: 0, failures: 0, peak_external: 0.0, peak_internal: 0}
+    }
+
+    /// Whether a request of `size` could ever be satisfied.
+    pub fn can_hold(&self, size: u32) -> bool {
+        self.rounded(size) <= self.size
+    }
+
+    pub fn address_of(&self, owner: usize) -> Option<u32> {
+        self.blocks.iter().find(|b| b.owner == Some(owner)).map(|b| b.start)
+    }
+
+    /// The hole the strategy would carve `size` out of.
+    fn hole_for(&self, size: u32) -> Option<usize> {
+        let holes = self.blocks.iter().enumerate().filter(|(_, b)| b.owner.is_none() && b.size >= size);
+        match self.strategy {
+            Strategy::FirstFit => holes.map(|(i, _)| i).next(),
+            Strategy::BestFit | Strategy::Buddy => holes.min_by_key(|(_, b)| (b.size, b.start)).map(|(i, _)| i),
+            Strategy::WorstFit => holes.max_by_key(|(_, b)| (b.size, std::cmp::Reverse(b.start))).map(|(i, _)| i),
+        }
+    }
+
+    fn rounded(&self, size: u32) -> u32 {
+        match self.strategy {
+            Strategy::Buddy => size.next_power_of_two(),
+            _ => size,
+        }
+    }
+
+    /// Whether some hole can take a request of `size` right now.
+    pub fn fits(&self, size: u32) -> bool {
+        self.hole_for(self.rounded(size)).is_some()
+    }
+
+    /// Gives `task` a block of at least the memory it asked for and returns
+    /// its address, if some hole fits.
+    pub fn allocate(&mut self, task: &Task) -> Option<u32> {
+        let size = task.memory;
+        let wanted = self.rounded(size);
+        let i = self.hole_for(wanted)?;
+        if self.strategy == Strategy::Buddy {
+            while self.blocks[i].size > wanted {
+                let half = self.blocks[i].size / 2;
+                self.blocks[i].size = half;
+                self.blocks.insert(i + 1, Block{start: self.blocks[i].start + half, size: half, owner: None, name: String::new(), requested: 0});
+            }
+        } else if self.blocks[i].size > wanted {
+            let rest = Block{start: self.blocks[i].start + wanted, size: self.blocks[i].size - wanted, owner: None, name: String::new(), requested: 0};
+            self.blocks[i].size = wanted;
+            self.blocks.insert(i + 1, rest);
+        }
+        self.blocks[i].owner = Some(task.id);
+        self.blocks[i].name = task.name.clone();
+        self.blocks[i].requested = size;
+        self.allocations += 1;
+        Some(self.blocks[i].start)
+    }
+
+    /// Gives back the block of task `owner`, if it has one, and merges the
+    /// holes around it.
+    pub fn free(&mut self, owner: usize) {
+        let mut i = match self.blocks.iter().position(|b| b.owner == Some(owner)) {
+            Some(i) => i,
+            None => return,
+        };
+        self.blocks[i].owner = None;
+        self.blocks[i].requested = 0;
+        if self.strategy == Strategy::Buddy {
+            loop {
+                let block = &self.blocks[i];
+                let buddy = block.start ^ block.size;
+                let j = if buddy > block.start { i + 1 } else { i.wrapping_sub(1) };
+                match self.blocks.get(j) {
+                    Some(b) if b.owner.is_none() && b.start == buddy && b.size == block.size => {
+                        let first = i.min(j);
+                        self.blocks[first].size *= 2;
+                        self.blocks.remove(first + 1);
+                        i = first;
+                    },
+                    _ => return,
+                }
+            }
+        }
+        if self.blocks.get(i + 1).is_some_and(|b| b.owner.is_none()) {
+            self.blocks[i].size += self.blocks.remove(i + 1).size;
+        }
+        if i > 0 && self.blocks[i - 1].owner.is_none() {
+            self.blocks[i - 1].size += self.blocks.remove(i).size;
+        }
+    }
+
+    pub fn free_units(&self) -> u32 {
+        self.blocks.iter().filter(|b| b.owner.is_none()).map(|b| b.size).sum()
+    }
+
+    pub fn largest_hole(&self) -> u32 {
+        self.blocks.iter().filter(|b| b.owner.is_none()).map(|b| b.size).max().unwrap_or(0)
+    }
+
+    /// Share of the free memory outside the largest hole, which no single
+    /// request can use all of.
+    pub fn external_fragmentation(&self) -> f64 {
+        let free = self.free_units();
+        if free == 0 { 0.0 } else { 1.0 - self.largest_hole() as f64 / free as f64 }
+    }
+
+    /// Units handed out beyond what tasks asked for.
+    pub fn internal_fragmentation(&self) -> u32 {
+        self.blocks.iter().filter(|b| b.owner.is_some()).map(|b| b.size - b.requested).sum()
+    }
+
+    pub fn empty(&self) -> bool {
+        self.blocks.len() == 1 && self.blocks[0].owner.is_none()
+    }
+
+    /// Folds the current fragmentation into the peaks; called once a clock.
+    pub fn sample(&mut self) {
+        self.peak_external = self.peak_external.max(self.external_fragmentation());
+        self.peak_internal = self.peak_internal.max(self.internal_fragmentation());
+    }
+}
+
+impl fmt::Display for MemoryPool {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[")?;
+        for (i, b) in self.blocks.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            match b.owner {
+                Some(_) => write!(f, "{}-{} {}", b.start, b.start + b.size - 1, b.name)?,
+                None => write!(f, "{}-{} free", b.start, b.start + b.size - 1)?,
+            }
+        }
+        write!(f, "]")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn task(id: usize, memory: u32) -> Task {
+        let mut task = Task::new(format!("T{}", id), None, 1);
+        task.id = id;
+        task.memory = memory;
+        task
+    }
+
+    /// Holes of 3, 2 and 5 units at 0, 4 and 7.
+    fn holes(strategy: Strategy) -> MemoryPool {
+        let mut pool = MemoryPool::new(12, strategy);
+        for (id, size) in [3, 1, 2, 1].iter().enumerate() {
+            pool.allocate(&task(id, *size)).unwrap();
+        }
+        pool.free(0);
+        pool.free(2);
+        pool
+    }
+
+    #[test]
+    fn strategies_pick_their_hole() {
+        assert_eq!(holes(Strategy::FirstFit).allocate(&task(9, 2)), Some(0));
+        assert_eq!(holes(Strategy::BestFit).allocate(&task(9, 2)), Some(4));
+        assert_eq!(holes(Strategy::WorstFit).allocate(&task(9, 2)), Some(7));
+    }
+
+    #[test]
+    fn freeing_coalesces_neighbours() {
+        let mut pool = holes(Strategy::FirstFit);
+        pool.free(1);
+        assert_eq!(pool.largest_hole(), 6);
+        pool.free(3);
+        assert!(pool.empty());
+    }
+
+    #[test]
+    fn fragmentation() {
+        let pool = holes(Strategy::FirstFit);
+        assert_eq!(pool.free_units(), 10);
+        assert!((pool.external_fragmentation() - 0.5).abs() < 1e-9);
+        assert_eq!(pool.internal_fragmentation(), 0);
+    }
+
+    #[test]
+    fn buddy_splits_and_merges_back() {
+        let mut pool = MemoryPool::new(16, Strategy::Buddy);
+        assert_eq!(pool.allocate(&task(0, 3)), Some(0));
+        assert_eq!(pool.allocate(&task(1, 5)), Some(8));
+        assert_eq!(pool.blocks.iter().map(|b| b.size).collect::<Vec<_>>(), vec![4, 4, 8]);
+        assert_eq!(pool.internal_fragmentation(), 4);
+        pool.free(0);
+        assert_eq!(pool.blocks.len(), 2);
+        pool.free(1);
+        assert!(pool.empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "power of two")]
+    fn buddy_needs_power_of_two() {
+        MemoryPool::new(12, Strategy::Buddy);
+    }
+}
@@ -1 +1,8 @@
 pub mod workers;
+pub mod scheduler;
+pub mod options;
+pub mod resources;
+pub mod deadlock;
+pub mod memory;
+pub mod paging;
+pub mod devices;
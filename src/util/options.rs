@@ -0,0 +1,37 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
+/// `key=value` settings given after the positional fields of an input line.
+#[derive(Debug, Default, Clone)]
+pub struct Options(HashMap<String, String>);
+
+impl Options {
+    pub fn parse<'a>(tokens: impl Iterator<Item = &'a str>) -> Options {
+        let mut options = HashMap::new();
+        for token in tokens.filter(|t| !t.is_empty()) {
+            match token.split_once('=') {
+                Some((key, value)) => {
+                    options.insert(key.to_string(), value.to_string());
+                },
+                None => panic!("WRONG INPUT! expected key=value, got {}", token)
+            }
+        }
+        Options(options)
+    }
+
+    pub fn get<T: FromStr>(&self, key: &str, default: T) -> T {
+        self.get_opt(key).unwrap_or(default)
+    }
+
+    pub fn get_opt<T: FromStr>(&self, key: &str) -> Option<T> {
+        self.0.get(key).map(|value| value.parse::<T>().unwrap_or_else(|_| panic!("WRONG INPUT! bad value for {}: {}", key, value)))
+    }
+
+    /// Reads a comma separated list, e.g. `quanta=1,2,4`.
+    pub fn get_list<T: FromStr>(&self, key: &str, default: Vec<T>) -> Vec<T> {
+        match self.0.get(key) {
+            Some(value) => value.split(',').map(|v| v.parse::<T>().unwrap_or_else(|_| panic!("WRONG INPUT! bad value for {}: {}", key, value))).collect(),
+            None => default
+        }
+    }
+}
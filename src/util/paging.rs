@@ -0,0 +1,251 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+use super::workers::Task;
+
+/// Which resident page makes room for one being faulted in.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Replacement {
+    /// The page loaded longest ago.
+    Fifo,
+    /// The page used longest ago.
+    Lru,
+    /// Second chance: a hand sweeps the frames, sparing and clearing those
+    /// referenced since it last passed.
+    Clock,
+    /// The page whose owner will use it again furthest in the future.
+    Optimal,
+}
+
+impl FromStr for Replacement {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Replacement, String> {
+        match s {
+            "fifo" => Ok(Replacement::Fifo),
+            "lru" => Ok(Replacement::Lru),
+            "clock" => Ok(Replacement::Clock),
+            "optimal" => Ok(Replacement::Optimal),
+            _ => Err(format!("unknown replacement policy {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub owner: usize,
+    pub name: String,
+    pub page: u32,
+    /// Access count at load and at last use, for FIFO and LRU.
+    pub loaded: u64,
+    pub used: u64,
+    pub referenced: bool,
+}
+
+/// What a reference to a page came to.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Access {
+    Hit,
+    /// The page was brought in, evicting `(owner, page)` if no frame was
+    /// free.
+    Fault(Option<(String, u32)>),
+}
+
+/// Physical frames shared by every task. Each task keeps at most its
+/// resident-set size of them and replaces among its own pages once it
+/// has that many; a task with none left takes a victim from anyone.
+#[derive(Debug, Clone)]
+pub struct FramePool {
+    pub frames: Vec<Option<Frame>>,
+    pub replacement: Replacement,
+    /// Clocks a faulting task waits for its page.
+    pub latency: u32,
+    hand: usize,
+    accesses: u64,
+    /// Each task's reference string and how far into it the task is, for
+    /// the optimal policy.
+    strings: BTreeMap<usize, (Vec<u32>, usize)>,
+    /// The page each task last faulted in, which its next reference gets
+    /// even if another fault evicted it during the wait; otherwise tasks
+    /// sharing too few frames would take each other's pages forever.
+    fetched: BTreeMap<usize, u32>,
+    pub faults: u32,
+}
+
+impl FramePool {
+    pub fn new(frames: usize, replacement: Replacement, latency: u32) -> FramePool {
+        assert!(frames > 0, "WRONG INPUT! paging needs at least one frame");
+        FramePool{frames: vec![None; frames], replacement, latency, hand: 0, accesses: 0, strings: BTreeMap::new(), fetched: BTreeMap::new(), faults: 0}
+    }
+
+    fn owned_by(&self, owner: usize) -> Vec<usize> {
+        (0..self.frames.len()).filter(|i| self.frames[*i].as_ref().is_some_and(|f| f.owner == owner)).collect()
+    }
+
+    /// References the page `task` touches in its next clock of work,
+    /// faulting it in if it isn't resident.
+    pub fn access(&mut self, task: &Task) -> Access {
+        let page = task.pages[task.time_executed as usize % task.pages.len()];
+        self.accesses += 1;
+        let accesses = self.accesses;
+        let at = task.time_executed as usize % task.pages.len();
+        self.strings.insert(task.id, (task.pages.clone(), at));
+        let fetched = self.fetched.remove(&task.id) == Some(page);
+        if let Some(frame) = self.frames.iter_mut().flatten().find(|f| f.owner == task.id && f.page == page) {
+            frame.used = accesses;
+            frame.referenced = true;
+            return Access::Hit;
+        }
+        if fetched {
+            return Access::Hit;
+        }
+        self.faults += 1;
+        self.fetched.insert(task.id, page);
+        let own = self.owned_by(task.id);
+        let free = self.frames.iter().position(|f| f.is_none());
+        let slot = match free {
+            Some(i) if own.len() < task.resident => i,
+            _ if !own.is_empty() => self.victim(&own),
+            Some(i) => i,
+            None => self.victim(&(0..self.frames.len()).collect::<Vec<_>>()),
+        };
+        let evicted = self.frames[slot].take().map(|f| (f.name, f.page));
+        self.frames[slot] = Some(Frame{owner: task.id, name: task.name.clone(), page, loaded: accesses, used: accesses, referenced: true});
+        Access::Fault(evicted)
+    }
+
+    /// Picks the frame among `candidates` the policy replaces.
+    fn victim(&mut self, candidates: &[usize]) -> usize {
+        let frame = |i: &usize| self.frames[*i].as_ref().unwrap();
+        match self.replacement {
+            Replacement::Fifo => *candidates.iter().min_by_key(|i| frame(i).loaded).unwrap(),
+            Replacement::Lru => *candidates.iter().min_by_key(|i| frame(i).used).unwrap(),
+            Replacement::Optimal => *candidates.iter().max_by_key(|i| (self.next_use(frame(i)), std::cmp::Reverse(**i))).unwrap(),
+            Replacement::Clock => loop {
+                let i = self.hand;
+                self.hand = (self.hand + 1) % self.frames.len();
+                if !candidates.contains(&i) {
+                    continue;
+                }
+                let f = self.frames[i].as_mut().unwrap();
+                if !f.referenced {
+                    return i;
+                }
+                f.referenced = false;
+            },
+        }
+    }
+
+    /// References until the owner of `frame` touches its page again; the
+    /// reference strings repeat, so every page comes back eventually.
+    fn next_use(&self, frame: &Frame) -> usize {
+        let (pages, at) = &self.strings[&frame.owner];
+        (1..=pages.len()).find(|d| pages[(at + d) % pages.len()] == frame.page).unwrap_or(usize::MAX)
+    }
+
+    /// Frees every frame of a task that left the system.
+    pub fn free(&mut self, owner: usize) {
+        for f in self.frames.iter_mut() {
+            if f.as_ref().is_some_and(|f| f.owner == owner) {
+                *f = None;
+            }
+        }
+        self.strings.remove(&owner);
+        self.fetched.remove(&owner);
+    }
+}
+
+impl fmt::Display for FramePool {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let frames: Vec<String> = self.frames.iter().map(|frame| match frame {
+            Some(frame) => format!("{}:{}", frame.name, frame.page),
+            None => String::from("-"),
+        }).collect();
+        write!(f, "[{}]", frames.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs one task through its reference string once on `frames` frames
+    /// and returns the page each fault evicted.
+    fn evictions(replacement: Replacement, frames: usize, pages: Vec<u32>) -> Vec<Option<u32>> {
+        let mut pool = FramePool::new(frames, replacement, 0);
+        let mut task = Task::new(String::from("T"), None, pages.len() as u16);
+        task.resident = frames;
+        task.pages = pages;
+        let mut evicted = Vec::new();
+        for at in 0..task.pages.len() {
+            task.time_executed = at as u16;
+            if let Access::Fault(victim) = pool.access(&task) {
+                evicted.push(victim.map(|(_, page)| page));
+            }
+        }
+        evicted
+    }
+
+    #[test]
+    fn fifo_evicts_oldest_load() {
+        assert_eq!(evictions(Replacement::Fifo, 3, vec![1, 2, 3, 4, 2, 5]), vec![None, None, None, Some(1), Some(2)]);
+    }
+
+    #[test]
+    fn lru_evicts_oldest_use() {
+        assert_eq!(evictions(Replacement::Lru, 3, vec![1, 2, 3, 1, 4]), vec![None, None, None, Some(2)]);
+        assert_eq!(evictions(Replacement::Lru, 3, vec![1, 2, 3, 4, 2, 5]), vec![None, None, None, Some(1), Some(3)]);
+    }
+
+    #[test]
+    fn clock_gives_second_chance() {
+        assert_eq!(evictions(Replacement::Clock, 3, vec![1, 2, 3, 4, 2, 5]), vec![None, None, None, Some(1), Some(3)]);
+    }
+
+    #[test]
+    fn optimal_evicts_furthest_next_use() {
+        assert_eq!(evictions(Replacement::Optimal, 3, vec![1, 2, 3, 4, 1, 2]), vec![None, None, None, Some(3)]);
+    }
+
+    #[test]
+    fn tasks_replace_among_their_own_pages() {
+        let mut pool = FramePool::new(3, Replacement::Fifo, 0);
+        let mut a = Task::new(String::from("A"), None, 3);
+        a.resident = 1;
+        a.pages = vec![1, 2];
+        let mut b = Task::new(String::from("B"), None, 3);
+        b.id = 1;
+        b.resident = 2;
+        b.pages = vec![7];
+        pool.access(&b);
+        pool.access(&a);
+        a.time_executed = 1;
+        assert_eq!(pool.access(&a), Access::Fault(Some((String::from("A"), 1))));
+        assert_eq!(pool.faults, 3);
+        pool.free(0);
+        assert_eq!(pool.frames.iter().flatten().count(), 1);
+    }
+
+    #[test]
+    fn tasks_sharing_one_frame_both_finish() {
+        let mut pool = FramePool::new(1, Replacement::Lru, 1);
+        let mut tasks: Vec<Task> = (0..2).map(|id| {
+            let mut task = Task::new(format!("T{}", id), None, 2);
+            task.id = id;
+            task.resident = 1;
+            task.pages = vec![id as u32 + 1];
+            task
+        }).collect();
+        // Every task faults and then steals the only frame from the other
+        // before it gets to use its page.
+        for _ in 0..8 {
+            for task in tasks.iter_mut().filter(|t| t.time_left() > 0) {
+                if pool.access(task) == Access::Hit {
+                    task.time_executed += 1;
+                }
+            }
+        }
+        assert!(tasks.iter().all(|t| t.time_left() == 0));
+        assert_eq!(pool.faults, 4);
+    }
+}
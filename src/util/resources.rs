@@ -0,0 +1,526 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::str::FromStr;
+use super::workers::Task;
+use super::memory::MemoryPool;
+use super::paging::FramePool;
+
+/// Index of a resource type in a `ResourcePool`.
+pub type ResourceId = usize;
+
+/// What a task taken off its core does with the resources it holds.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Preemption {
+    /// Give everything back; the task acquires it again when redispatched.
+    Release,
+    /// Keep everything while sitting in the ready queue.
+    Hold,
+}
+
+impl FromStr for Preemption {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Preemption, String> {
+        match s {
+            "release" => Ok(Preemption::Release),
+            "hold" => Ok(Preemption::Hold),
+            _ => Err(format!("unknown preemption policy {}", s)),
+        }
+    }
+}
+
+/// Locking protocol used against priority inversion.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Protocol {
+    None,
+    /// A holder runs at the priority of the most important task it blocks.
+    Inherit,
+    /// Immediate priority ceiling: a holder runs at the priority of the
+    /// most important task that may ever use what it holds.
+    Ceiling,
+}
+
+impl FromStr for Protocol {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Protocol, String> {
+        match s {
+            "none" => Ok(Protocol::None),
+            "inherit" => Ok(Protocol::Inherit),
+            "ceiling" => Ok(Protocol::Ceiling),
+            _ => Err(format!("unknown protocol {}", s)),
+        }
+    }
+}
+
+/// Order in which the master looks through the waiting queue each clock.
+/// Every task it finds that can be granted what it waits for gets it and
+/// goes back to the scheduler.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Wakeup {
+    /// Queue order, stopping at the first task that still can't run so
+    /// nobody overtakes it, except tasks holding units of something it
+    /// asks for: it only gets those back once they have run.
+    Fifo,
+    /// Least time left first.
+    Shortest,
+    /// Most important first.
+    Priority,
+    /// Queue order, skipping over tasks that can't run.
+    FirstFit,
+}
+
+impl FromStr for Wakeup {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Wakeup, String> {
+        match s {
+            "fifo" => Ok(Wakeup::Fifo),
+            "shortest" => Ok(Wakeup::Shortest),
+            "priority" => Ok(Wakeup::Priority),
+            "firstfit" => Ok(Wakeup::FirstFit),
+            _ => Err(format!("unknown wake-up policy {}", s)),
+        }
+    }
+}
+
+/// What one task in the system has declared and currently holds.
+#[derive(Debug, Clone)]
+pub struct Claim {
+    pub name: String,
+    pub max: Vec<u16>,
+    pub held: Vec<u16>,
+    /// The task's own priority.
+    pub priority: u16,
+    /// The priority it runs at under the locking protocol.
+    pub effective: u16,
+}
+
+/// A named set of resource types, each with a fixed number of units, and
+/// the claims of every task currently in the system.
+#[derive(Debug, Clone)]
+pub struct ResourcePool {
+    pub names: Vec<String>,
+    pub total: Vec<u16>,
+    pub available: Vec<u16>,
+    pub claims: BTreeMap<usize, Claim>,
+    /// Only grant requests that leave the system in a safe state.
+    pub banker: bool,
+    pub preemption: Preemption,
+    pub protocol: Protocol,
+    pub wakeup: Wakeup,
+    /// Contiguous memory tasks get a block of for their whole run.
+    pub memory: Option<MemoryPool>,
+    /// Frames for tasks that page through a reference string.
+    pub paging: Option<FramePool>,
+    /// Most important priority of any task that has claimed each resource.
+    pub ceilings: Vec<u16>,
+}
+
+impl ResourcePool {
+    pub fn new(resources: Vec<(String, u16)>) -> ResourcePool {
+        let (names, total): (Vec<String>, Vec<u16>) = resources.into_iter().unzip();
+        let ceilings = vec![u16::MAX; names.len()];
+        ResourcePool{names, available: total.clone(), total, claims: BTreeMap::new(), banker: false, preemption: Preemption::Release, protocol: Protocol::None, wakeup: Wakeup::FirstFit, memory: None, paging: None, ceilings}
+    }
+
+    /// Whether every unit, and all of memory, is back in the pool.
+    pub fn balanced(&self) -> bool {
+        self.available == self.total && self.memory.as_ref().is_none_or(|m| m.empty())
+    }
+
+    /// Whether `task` has the memory it needs, or could get it right now.
+    pub fn memory_fits(&self, task: &Task) -> bool {
+        match &self.memory {
+            Some(m) if task.memory > 0 => m.address_of(task.id).is_some() || m.fits(task.memory),
+            _ => true,
+        }
+    }
+
+    /// Gives `task` its block of memory unless it has one already, returning
+    /// false and counting a failure when no hole fits.
+    pub fn allocate_memory(&mut self, task: &Task) -> bool {
+        let m = match &mut self.memory {
+            Some(m) if task.memory > 0 => m,
+            _ => return true,
+        };
+        if m.address_of(task.id).is_some() || m.allocate(task).is_some() {
+            return true;
+        }
+        m.failures += 1;
+        false
+    }
+
+    /// `[(A, 2), (C, 3)]` for the non-zero entries of a demand vector.
+    pub fn describe(&self, need: &[u16]) -> String {
+        let entries: Vec<String> = need.iter().enumerate().filter(|(_, n)| **n > 0).map(|(r, n)| format!("({}, {})", self.names[r], n)).collect();
+        format!("[{}]", entries.join(", "))
+    }
+
+    /// In banker mode, rejects a task whose maximum claim is more than the
+    /// pool has.
+    pub fn check_claim(&self, task: &Task) {
+        if self.banker {
+            assert!(task.max_claim.iter().zip(self.total.iter()).all(|(m, t)| m <= t), "WRONG INPUT! {} claims more than exists", task.name);
+        }
+    }
+
+    /// Records a task entering the system with its maximum claim.
+    pub fn register(&mut self, task: &Task) {
+        self.check_claim(task);
+        let mut max = task.max_claim.clone();
+        max.resize(self.names.len(), 0);
+        self.raise_ceilings(task);
+        let claim = Claim{name: task.name.clone(), held: vec![0; max.len()], max, priority: task.base_priority, effective: task.base_priority};
+        self.claims.insert(task.id, claim);
+    }
+
+    /// Makes every resource `task` may claim at least as important as it.
+    /// Periodic templates go through here up front so their jobs count
+    /// before they are first released.
+    pub fn raise_ceilings(&mut self, task: &Task) {
+        for (c, m) in self.ceilings.iter_mut().zip(task.max_claim.iter()) {
+            if *m > 0 {
+                *c = (*c).min(task.base_priority);
+            }
+        }
+    }
+
+    /// The priority task `id` should run at, if it is in the system.
+    pub fn effective_priority(&self, id: usize) -> Option<u16> {
+        self.claims.get(&id).map(|c| c.effective)
+    }
+
+    /// Recomputes every task's effective priority under the protocol.
+    /// Inheritance is transitive: a holder blocked in turn passes what it
+    /// inherited on to whoever blocks it.
+    pub fn update_priorities(&mut self, waiting: &VecDeque<Task>) {
+        for claim in self.claims.values_mut() {
+            claim.effective = claim.priority;
+            if self.protocol == Protocol::Ceiling {
+                for (h, c) in claim.held.iter().zip(self.ceilings.iter()) {
+                    if *h > 0 {
+                        claim.effective = claim.effective.min(*c);
+                    }
+                }
+            }
+        }
+        if self.protocol != Protocol::Inherit {
+            return;
+        }
+        loop {
+            let mut changed = false;
+            for w in waiting {
+                let from = match self.claims.get(&w.id) {
+                    Some(c) => c.effective,
+                    None => continue,
+                };
+                for h in self.blockers(w) {
+                    let holder = self.claims.get_mut(&h).unwrap();
+                    if from < holder.effective {
+                        holder.effective = from;
+                        changed = true;
+                    }
+                }
+            }
+            if !changed {
+                return;
+            }
+        }
+    }
+
+    /// Tasks holding units of a resource `waiter` is short of.
+    pub fn blockers(&self, waiter: &Task) -> Vec<usize> {
+        let request = waiter.request();
+        let short: Vec<ResourceId> = (0..request.len()).filter(|r| request[*r] > self.available[*r]).collect();
+        self.claims.iter()
+            .filter(|(id, c)| **id != waiter.id && short.iter().any(|r| c.held[*r] > 0))
+            .map(|(id, _)| *id)
+            .collect()
+    }
+
+    /// Whether `waiter` is blocked by a task less important than itself.
+    pub fn inverted(&self, waiter: &Task) -> bool {
+        self.blockers(waiter).iter().any(|h| self.claims[h].priority > waiter.base_priority)
+    }
+
+    /// Forgets a task that left the system and frees its memory; it must
+    /// not hold any other resource.
+    pub fn retire(&mut self, id: usize) {
+        self.claims.remove(&id);
+        if let Some(m) = &mut self.memory {
+            m.free(id);
+        }
+        if let Some(frames) = &mut self.paging {
+            frames.free(id);
+        }
+    }
+
+    /// Takes the tasks the wake-up policy finds runnable out of `waiting`,
+    /// granting each what it waits for before looking at the next. Tasks
+    /// still waiting for a page at `clock`, or joining children not yet in
+    /// `exited`, are passed over. In banker mode each task comes with the
+    /// safe sequence its grant was checked against.
+    pub fn wake(&mut self, waiting: &mut VecDeque<Task>, clock: u32, exited: &[usize]) -> Vec<(Task, Option<String>)> {
+        let mut order: Vec<usize> = (0..waiting.len()).collect();
+        match self.wakeup {
+            Wakeup::Shortest => order.sort_by_key(|i| (waiting[*i].time_left(), waiting[*i].id)),
+            Wakeup::Priority => order.sort_by_key(|i| (waiting[*i].priority, waiting[*i].id)),
+            Wakeup::Fifo | Wakeup::FirstFit => {},
+        }
+        let mut woken = Vec::new();
+        let mut grants = Vec::new();
+        // What the first task FIFO couldn't wake asks for.
+        let mut head: Option<Vec<u16>> = None;
+        for i in order {
+            if waiting[i].blocked_until > clock || !waiting[i].joined(exited) {
+                continue;
+            }
+            if let Some(head) = &head {
+                if !waiting[i].held.iter().zip(head.iter()).any(|(h, n)| *h > 0 && *n > 0) {
+                    continue;
+                }
+            }
+            let request = waiting[i].request();
+            if self.memory_fits(&waiting[i]) && self.can_grant(waiting[i].id, &request) {
+                grants.push(if self.banker {
+                    let sequence = self.safe_sequence_after(waiting[i].id, &request).unwrap();
+                    Some(format!("granted {} to {}, safe sequence: <{}>", self.describe(&request), waiting[i].name, sequence.join(", ")))
+                } else {
+                    None
+                });
+                self.allocate_memory(&waiting[i]);
+                acquire(self, &mut waiting[i], &request);
+                woken.push(i);
+            } else if self.wakeup == Wakeup::Fifo && head.is_none() {
+                head = Some(request);
+            }
+        }
+        let mut slots: Vec<Option<Task>> = waiting.drain(..).map(Some).collect();
+        let tasks = woken.iter().map(|i| slots[*i].take().unwrap()).zip(grants).collect();
+        waiting.extend(slots.into_iter().flatten());
+        tasks
+    }
+
+    /// Whether `need` can be given to task `id` now: enough units are free
+    /// and, in banker mode, the state after granting it is safe.
+    pub fn can_grant(&self, id: usize, need: &[u16]) -> bool {
+        check_for_enough_resourse(self, need) && (!self.banker || self.safe_sequence_after(id, need).is_some())
+    }
+
+    /// Runs the banker's safety check as if `need` were granted to `id`,
+    /// returning an order in which every task could still finish.
+    pub fn safe_sequence_after(&self, id: usize, need: &[u16]) -> Option<Vec<String>> {
+        let mut work = self.available.clone();
+        let mut held: BTreeMap<usize, Vec<u16>> = self.claims.iter().map(|(i, c)| (*i, c.held.clone())).collect();
+        for (r, n) in need.iter().enumerate() {
+            work[r] = work[r].checked_sub(*n)?;
+            if let Some(h) = held.get_mut(&id) {
+                h[r] += *n;
+            }
+        }
+        let mut sequence = Vec::new();
+        while !held.is_empty() {
+            let next = held.iter().find(|(i, h)| {
+                let max = &self.claims[*i].max;
+                max.iter().zip(h.iter()).zip(work.iter()).all(|((m, h), w)| m.saturating_sub(*h) <= *w)
+            }).map(|(i, _)| *i)?;
+            for (w, h) in work.iter_mut().zip(held.remove(&next).unwrap()) {
+                *w += h;
+            }
+            sequence.push(self.claims[&next].name.clone());
+        }
+        Some(sequence)
+    }
+
+    pub fn id(&self, name: &str) -> Option<ResourceId> {
+        self.names.iter().position(|n| n == name)
+    }
+
+    /// Turns `NAME[:COUNT]` entries, e.g. `["A:2", "C:3"]`, into a demand
+    /// vector indexed by resource id. A missing count means one unit.
+    pub fn demand(&self, entries: &[String]) -> Vec<u16> {
+        let mut demand = vec![0; self.names.len()];
+        for entry in entries {
+            let (name, count) = match entry.split_once(':') {
+                Some((name, count)) => (name, count.parse::<u16>().unwrap_or_else(|_| panic!("WRONG INPUT! bad count in {}", entry))),
+                None => (entry.as_str(), 1),
+            };
+            let r = self.id(name).unwrap_or_else(|| panic!("WRONG INPUT! unknown resource {}", name));
+            demand[r] += count;
+        }
+        demand
+    }
+}
+
+impl fmt::Display for ResourcePool {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[")?;
+        for (i, name) in self.names.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "({}, {}/{})", name, self.available[i], self.total[i])?;
+        }
+        write!(f, "]")
+    }
+}
+
+// Demand vectors are indexed by `ResourceId`; missing trailing entries
+// mean zero units.
+
+pub fn check_for_enough_resourse(have: &ResourcePool, need: &[u16]) -> bool {
+    need.iter().enumerate().all(|(r, n)| have.available[r] >= *n)
+}
+
+pub fn release_resources(have: &mut ResourcePool, id: usize, need: &[u16]) {
+    for (r, n) in need.iter().enumerate() {
+        have.available[r] += *n;
+        if let Some(claim) = have.claims.get_mut(&id) {
+            claim.held[r] -= *n;
+        }
+    }
+}
+
+pub fn require_resources(have: &mut ResourcePool, id: usize, need: &[u16]) {
+    for (r, n) in need.iter().enumerate() {
+        have.available[r] -= *n;
+        if let Some(claim) = have.claims.get_mut(&id) {
+            claim.held[r] += *n;
+        }
+    }
+}
+
+/// Gives `task` the units in `need`, keeping its own tally in step with the
+/// pool's.
+pub fn acquire(have: &mut ResourcePool, task: &mut Task, need: &[u16]) {
+    require_resources(have, task.id, need);
+    if task.held.len() < need.len() {
+        task.held.resize(need.len(), 0);
+    }
+    for (h, n) in task.held.iter_mut().zip(need.iter()) {
+        *h += *n;
+    }
+    if have.protocol == Protocol::Ceiling {
+        if let Some(claim) = have.claims.get_mut(&task.id) {
+            for (r, n) in need.iter().enumerate() {
+                if *n > 0 {
+                    claim.effective = claim.effective.min(have.ceilings[r]);
+                }
+            }
+            task.priority = claim.effective;
+        }
+    }
+}
+
+/// Takes back everything `task` holds and returns what that was.
+pub fn release_all(have: &mut ResourcePool, task: &mut Task) -> Vec<u16> {
+    let held = std::mem::take(&mut task.held);
+    release_resources(have, task.id, &held);
+    held
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn holding(pool: &mut ResourcePool, id: usize, max: u16) -> Task {
+        let mut task = Task::new(format!("T{}", id), None, 1);
+        task.id = id;
+        task.max_claim = vec![max];
+        pool.register(&task);
+        acquire(pool, &mut task, &[1]);
+        task
+    }
+
+    /// Three units of A; T0 may claim all three and T1 two, and each
+    /// holds one.
+    fn pool() -> (ResourcePool, Task, Task) {
+        let mut pool = ResourcePool::new(vec![(String::from("A"), 3)]);
+        pool.banker = true;
+        let t0 = holding(&mut pool, 0, 3);
+        let t1 = holding(&mut pool, 1, 2);
+        (pool, t0, t1)
+    }
+
+    #[test]
+    fn safe_grant_has_a_sequence() {
+        let (pool, _, t1) = pool();
+        assert_eq!(pool.safe_sequence_after(t1.id, &[1]), Some(vec![String::from("T1"), String::from("T0")]));
+        assert!(pool.can_grant(t1.id, &[1]));
+    }
+
+    #[test]
+    fn unsafe_grant_is_refused() {
+        let (pool, t0, _) = pool();
+        assert_eq!(pool.safe_sequence_after(t0.id, &[1]), None);
+        assert!(!pool.can_grant(t0.id, &[1]));
+    }
+
+    #[test]
+    fn grant_beyond_available_is_refused() {
+        let (pool, _, t1) = pool();
+        assert_eq!(pool.safe_sequence_after(t1.id, &[2]), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "claims more than exists")]
+    fn claim_beyond_total_is_rejected() {
+        let (mut pool, _, _) = pool();
+        holding(&mut pool, 2, 4);
+    }
+
+    #[test]
+    #[should_panic(expected = "T2 claims more than exists")]
+    fn claim_is_checked_without_registering() {
+        let (pool, _, _) = pool();
+        let mut task = Task::new(String::from("T2"), None, 1);
+        task.max_claim = vec![4];
+        pool.check_claim(&task);
+    }
+
+    #[test]
+    fn release_all_balances_pool() {
+        let (mut pool, mut t0, mut t1) = pool();
+        assert_eq!(release_all(&mut pool, &mut t0), vec![1]);
+        release_all(&mut pool, &mut t1);
+        assert!(pool.balanced());
+        assert!(pool.claims.values().all(|c| c.held == vec![0]));
+    }
+
+    #[test]
+    fn wake_reports_banker_grants() {
+        for banker in [false, true] {
+            let mut pool = ResourcePool::new(vec![(String::from("A"), 1)]);
+            pool.banker = banker;
+            let mut task = Task::new(String::from("T0"), None, 1);
+            task.resourses = vec![1];
+            task.max_claim = vec![1];
+            pool.register(&task);
+            let mut waiting = VecDeque::from(vec![task]);
+            let woken = pool.wake(&mut waiting, 0, &[]);
+            assert!(waiting.is_empty());
+            assert_eq!(woken[0].0.held, vec![1]);
+            let grant = banker.then(|| String::from("granted [(A, 1)] to T0, safe sequence: <T0>"));
+            assert_eq!(woken[0].1, grant);
+        }
+    }
+
+    #[test]
+    fn fifo_lets_holders_past_a_blocked_head() {
+        let mut pool = ResourcePool::new(vec![(String::from("A"), 2)]);
+        pool.wakeup = Wakeup::Fifo;
+        // T1 needs both units, and T0 behind it holds one of them.
+        let mut waiting: VecDeque<Task> = [("T1", 2, 0), ("T0", 2, 1), ("T2", 1, 0)].iter().enumerate().map(|(id, (name, need, held))| {
+            let mut task = Task::new(name.to_string(), None, 1);
+            task.id = id;
+            task.resourses = vec![*need];
+            pool.register(&task);
+            acquire(&mut pool, &mut task, &[*held]);
+            task
+        }).collect();
+        let woken = pool.wake(&mut waiting, 0, &[]);
+        assert_eq!(woken.iter().map(|(t, _)| t.name.as_str()).collect::<Vec<_>>(), vec!["T0"]);
+        assert_eq!(waiting.iter().map(|t| t.name.as_str()).collect::<Vec<_>>(), vec!["T1", "T2"]);
+    }
+}
@@ -0,0 +1,1161 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use super::workers::{Algorithm, Task, TaskType};
+use super::options::Options;
+
+/// A scheduling policy. `cpu_worker` and `master_worker` only talk to the
+/// ready set through these hooks, so a new policy never has to touch the
+/// threading or barrier code.
+pub trait Scheduler: Send {
+    /// Puts a task that just entered the system into the ready set.
+    fn admit(&mut self, task: Task);
+
+    /// Takes the task a free core should run next out of the ready set.
+    fn pick_next(&mut self) -> Option<Task>;
+
+    /// Called after `task` ran for a clock without finishing, `ran` being the
+    /// clocks it has had since it was dispatched; returning true takes it off
+    /// its core and hands it back through `on_preempt`.
+    fn on_tick(&mut self, _task: &mut Task, _ran: u16) -> bool {
+        false
+    }
+
+    /// Called at the start of every clock for the task on a core, before it
+    /// runs; returning true hands it back through `on_preempt` right away
+    /// so a more urgent ready task gets the core this clock.
+    fn should_preempt(&self, _task: &Task) -> bool {
+        false
+    }
+
+    /// Puts a task that was taken off its core back into the ready set.
+    fn on_preempt(&mut self, task: Task) {
+        self.admit(task);
+    }
+
+    /// Called once `task` has executed all of its time.
+    fn on_complete(&mut self, _task: &Task) {}
+
+    /// Parks a task that could not get its resources in the waiting queue.
+    fn on_block(&mut self, task: Task, waiting: &mut VecDeque<Task>) {
+        waiting.push_back(task);
+    }
+
+    /// Puts a task coming out of the waiting queue back into the ready set.
+    fn on_wakeup(&mut self, task: Task) {
+        self.admit(task);
+    }
+
+    /// Called after the priorities of ready tasks changed under them.
+    fn reorder(&mut self) {}
+
+    /// Called by the master at the start of every clock, before the cores run.
+    fn on_clock(&mut self, _clock: u32) {}
+
+    /// The ready queues by name, in the order they should be printed.
+    fn queues(&self) -> Vec<(String, Vec<&Task>)>;
+
+    /// Every task in the ready set, for per-clock bookkeeping.
+    fn tasks_mut(&mut self) -> Vec<&mut Task>;
+
+    fn is_empty(&self) -> bool;
+}
+
+impl Algorithm {
+    /// Builds the policy, reading its settings (e.g. `quantum=4`) from the
+    /// options given on the algorithm line.
+    pub fn scheduler(self, options: &Options) -> Box<dyn Scheduler> {
+        match self {
+            Algorithm::FCFS => Box::new(Fcfs::default()),
+            Algorithm::SJF => Box::new(Sjf::default()),
+            Algorithm::RR => Box::new(RoundRobin::new(options.get("quantum", 1))),
+            Algorithm::MLQ => Box::new(Mlq::default()),
+            Algorithm::SRTF => Box::new(Srtf::default()),
+            Algorithm::Priority { preemptive } => Box::new(Priority::new(preemptive)),
+            Algorithm::HRRN => Box::new(Hrrn::default()),
+            Algorithm::Lottery => Box::new(Lottery::new(options.get("quantum", 1), options.get("seed", 1))),
+            Algorithm::Stride => Box::new(Stride::new(options.get("quantum", 1))),
+            Algorithm::CFS => Box::new(Cfs::new(options.get("latency", 6), options.get("granularity", 1))),
+            Algorithm::EDF => Box::new(Edf::default()),
+            Algorithm::RM => Box::new(RateMonotonic::default()),
+            Algorithm::MLFQ => {
+                let given: Vec<u16> = options.get_list("quanta", Vec::new());
+                let levels = options.get("levels", if given.is_empty() { 3 } else { given.len() });
+                let quanta = (0..levels).map(|l| given.get(l).or(given.last()).copied().unwrap_or(1u16.checked_shl(l as u32).unwrap_or(u16::MAX))).collect();
+                Box::new(Mlfq::new(quanta, options.get("boost", 0)))
+            },
+        }
+    }
+}
+
+fn sort_by_total_time(q: &mut VecDeque<Task>) {
+    q.make_contiguous().sort_by_key(|t| t.total_time);
+}
+
+#[derive(Default)]
+pub struct Fcfs {
+    queue: VecDeque<Task>,
+}
+
+impl Scheduler for Fcfs {
+    fn admit(&mut self, task: Task) {
+        self.queue.push_back(task);
+    }
+
+    fn pick_next(&mut self) -> Option<Task> {
+        self.queue.pop_front()
+    }
+
+    fn tasks_mut(&mut self) -> Vec<&mut Task> {
+        self.queue.iter_mut().collect()
+    }
+
+    fn queues(&self) -> Vec<(String, Vec<&Task>)> {
+        vec![(String::from("QUEUE"), self.queue.iter().collect())]
+    }
+
+    fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+}
+
+#[derive(Default)]
+pub struct Sjf {
+    queue: VecDeque<Task>,
+}
+
+impl Scheduler for Sjf {
+    fn admit(&mut self, task: Task) {
+        self.queue.push_back(task);
+        sort_by_total_time(&mut self.queue);
+    }
+
+    fn pick_next(&mut self) -> Option<Task> {
+        self.queue.pop_front()
+    }
+
+    fn on_block(&mut self, task: Task, waiting: &mut VecDeque<Task>) {
+        waiting.push_back(task);
+        sort_by_total_time(waiting);
+    }
+
+    fn tasks_mut(&mut self) -> Vec<&mut Task> {
+        self.queue.iter_mut().collect()
+    }
+
+    fn queues(&self) -> Vec<(String, Vec<&Task>)> {
+        vec![(String::from("QUEUE"), self.queue.iter().collect())]
+    }
+
+    fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+}
+
+fn sort_by_time_left(q: &mut VecDeque<Task>) {
+    q.make_contiguous().sort_by_key(|t| t.time_left());
+}
+
+/// Preemptive SJF: the ready queue is kept ordered by time left, and a
+/// running task gives up its core (and its resources) as soon as the head
+/// of the queue would finish sooner.
+#[derive(Default)]
+pub struct Srtf {
+    queue: VecDeque<Task>,
+}
+
+impl Scheduler for Srtf {
+    fn admit(&mut self, task: Task) {
+        self.queue.push_back(task);
+        sort_by_time_left(&mut self.queue);
+    }
+
+    fn pick_next(&mut self) -> Option<Task> {
+        self.queue.pop_front()
+    }
+
+    fn should_preempt(&self, task: &Task) -> bool {
+        match self.queue.front() {
+            Some(head) => head.time_left() < task.time_left(),
+            None => false,
+        }
+    }
+
+    fn on_block(&mut self, task: Task, waiting: &mut VecDeque<Task>) {
+        waiting.push_back(task);
+        sort_by_time_left(waiting);
+    }
+
+    fn tasks_mut(&mut self) -> Vec<&mut Task> {
+        self.queue.iter_mut().collect()
+    }
+
+    fn queues(&self) -> Vec<(String, Vec<&Task>)> {
+        vec![(String::from("QUEUE"), self.queue.iter().collect())]
+    }
+
+    fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+}
+
+fn sort_by_priority(q: &mut VecDeque<Task>) {
+    q.make_contiguous().sort_by_key(|t| (t.priority, t.id));
+}
+
+/// Runs the task with the lowest priority number first, ties going to the
+/// one that came first in the input. The waiting queue is kept in the same
+/// order so the most important blocked task is retried first. When
+/// preemptive, a running task gives up its core as soon as a strictly more
+/// important task is ready.
+pub struct Priority {
+    queue: VecDeque<Task>,
+    preemptive: bool,
+}
+
+impl Priority {
+    pub fn new(preemptive: bool) -> Priority {
+        Priority{queue: VecDeque::new(), preemptive}
+    }
+}
+
+impl Scheduler for Priority {
+    fn admit(&mut self, task: Task) {
+        self.queue.push_back(task);
+        sort_by_priority(&mut self.queue);
+    }
+
+    fn pick_next(&mut self) -> Option<Task> {
+        self.queue.pop_front()
+    }
+
+    fn should_preempt(&self, task: &Task) -> bool {
+        match self.queue.front() {
+            Some(head) => self.preemptive && head.priority < task.priority,
+            None => false,
+        }
+    }
+
+    fn on_block(&mut self, task: Task, waiting: &mut VecDeque<Task>) {
+        waiting.push_back(task);
+        sort_by_priority(waiting);
+    }
+
+    fn reorder(&mut self) {
+        sort_by_priority(&mut self.queue);
+    }
+
+    fn tasks_mut(&mut self) -> Vec<&mut Task> {
+        self.queue.iter_mut().collect()
+    }
+
+    fn queues(&self) -> Vec<(String, Vec<&Task>)> {
+        vec![(String::from("QUEUE"), self.queue.iter().collect())]
+    }
+
+    fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+}
+
+fn sort_by_deadline(q: &mut VecDeque<Task>) {
+    q.make_contiguous().sort_by_key(|t| (t.deadline.unwrap_or(u32::MAX), t.id));
+}
+
+/// Earliest deadline first: the ready task due soonest always runs, taking
+/// a core from a running task with a later deadline. Tasks without a
+/// deadline go after every task that has one.
+#[derive(Default)]
+pub struct Edf {
+    queue: VecDeque<Task>,
+}
+
+impl Scheduler for Edf {
+    fn admit(&mut self, task: Task) {
+        self.queue.push_back(task);
+        sort_by_deadline(&mut self.queue);
+    }
+
+    fn pick_next(&mut self) -> Option<Task> {
+        self.queue.pop_front()
+    }
+
+    fn should_preempt(&self, task: &Task) -> bool {
+        match self.queue.front() {
+            Some(head) => head.deadline.unwrap_or(u32::MAX) < task.deadline.unwrap_or(u32::MAX),
+            None => false,
+        }
+    }
+
+    fn on_block(&mut self, task: Task, waiting: &mut VecDeque<Task>) {
+        waiting.push_back(task);
+        sort_by_deadline(waiting);
+    }
+
+    fn tasks_mut(&mut self) -> Vec<&mut Task> {
+        self.queue.iter_mut().collect()
+    }
+
+    fn queues(&self) -> Vec<(String, Vec<&Task>)> {
+        vec![(String::from("QUEUE"), self.queue.iter().collect())]
+    }
+
+    fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+}
+
+fn sort_by_period(q: &mut VecDeque<Task>) {
+    q.make_contiguous().sort_by_key(|t| (t.period.unwrap_or(u32::MAX), t.id));
+}
+
+/// Rate monotonic: fixed priorities from the period, shortest first, with
+/// one-shot tasks below every periodic job. Preemptive.
+#[derive(Default)]
+pub struct RateMonotonic {
+    queue: VecDeque<Task>,
+}
+
+impl Scheduler for RateMonotonic {
+    fn admit(&mut self, task: Task) {
+        self.queue.push_back(task);
+        sort_by_period(&mut self.queue);
+    }
+
+    fn pick_next(&mut self) -> Option<Task> {
+        self.queue.pop_front()
+    }
+
+    fn should_preempt(&self, task: &Task) -> bool {
+        match self.queue.front() {
+            Some(head) => head.period.unwrap_or(u32::MAX) < task.period.unwrap_or(u32::MAX),
+            None => false,
+        }
+    }
+
+    fn on_block(&mut self, task: Task, waiting: &mut VecDeque<Task>) {
+        waiting.push_back(task);
+        sort_by_period(waiting);
+    }
+
+    fn tasks_mut(&mut self) -> Vec<&mut Task> {
+        self.queue.iter_mut().collect()
+    }
+
+    fn queues(&self) -> Vec<(String, Vec<&Task>)> {
+        vec![(String::from("QUEUE"), self.queue.iter().collect())]
+    }
+
+    fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+}
+
+/// Highest response ratio next: non-preemptive, and at every dispatch picks
+/// the ready task with the largest (waiting + service) / service, so long
+/// jobs climb up the order the longer they wait.
+#[derive(Default)]
+pub struct Hrrn {
+    queue: VecDeque<Task>,
+}
+
+impl Scheduler for Hrrn {
+    fn admit(&mut self, task: Task) {
+        self.queue.push_back(task);
+    }
+
+    fn pick_next(&mut self) -> Option<Task> {
+        // Compares (w1 + s1) / s1 against (w2 + s2) / s2 by cross multiplying.
+        let ratio = |t: &Task| (t.waiting_time as u64 + t.total_time as u64, (t.total_time as u64).max(1));
+        let mut best: Option<usize> = None;
+        for (i, t) in self.queue.iter().enumerate() {
+            let better = match best {
+                Some(b) => {
+                    let (n1, d1) = ratio(t);
+                    let (n2, d2) = ratio(&self.queue[b]);
+                    n1 * d2 > n2 * d1
+                },
+                None => true,
+            };
+            if better {
+                best = Some(i);
+            }
+        }
+        best.and_then(|i| self.queue.remove(i))
+    }
+
+    fn tasks_mut(&mut self) -> Vec<&mut Task> {
+        self.queue.iter_mut().collect()
+    }
+
+    fn queues(&self) -> Vec<(String, Vec<&Task>)> {
+        vec![(String::from("QUEUE"), self.queue.iter().collect())]
+    }
+
+    fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+}
+
+/// A dispatched task keeps its core for `quantum` clocks, then goes to the
+/// back of the queue if it still has work left.
+pub struct RoundRobin {
+    queue: VecDeque<Task>,
+    quantum: u16,
+}
+
+impl RoundRobin {
+    pub fn new(quantum: u16) -> RoundRobin {
+        assert!(quantum > 0, "WRONG INPUT! quantum must be at least 1");
+        RoundRobin{queue: VecDeque::new(), quantum}
+    }
+}
+
+impl Scheduler for RoundRobin {
+    fn admit(&mut self, task: Task) {
+        self.queue.push_back(task);
+    }
+
+    fn pick_next(&mut self) -> Option<Task> {
+        self.queue.pop_front()
+    }
+
+    fn on_tick(&mut self, _task: &mut Task, ran: u16) -> bool {
+        ran >= self.quantum
+    }
+
+    fn tasks_mut(&mut self) -> Vec<&mut Task> {
+        self.queue.iter_mut().collect()
+    }
+
+    fn queues(&self) -> Vec<(String, Vec<&Task>)> {
+        vec![(String::from("QUEUE"), self.queue.iter().collect())]
+    }
+
+    fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+}
+
+/// Three fixed levels keyed by task type: Z and Y are round robin and
+/// always win over X, which runs FCFS and is pushed back to the head of
+/// its queue whenever a Y or Z task shows up. Unlabeled tasks go with X.
+#[derive(Default)]
+pub struct Mlq {
+    x: VecDeque<Task>,
+    y: VecDeque<Task>,
+    z: VecDeque<Task>,
+}
+
+impl Scheduler for Mlq {
+    fn admit(&mut self, task: Task) {
+        match task.task_type {
+            Some(TaskType::Z) => self.z.push_back(task),
+            Some(TaskType::Y) => self.y.push_back(task),
+            _ => self.x.push_back(task),
+        }
+    }
+
+    fn pick_next(&mut self) -> Option<Task> {
+        self.z.pop_front().or_else(|| self.y.pop_front()).or_else(|| self.x.pop_front())
+    }
+
+    fn on_tick(&mut self, task: &mut Task, _ran: u16) -> bool {
+        matches!(task.task_type, Some(TaskType::Y) | Some(TaskType::Z))
+    }
+
+    fn should_preempt(&self, task: &Task) -> bool {
+        match task.task_type {
+            Some(TaskType::Y) | Some(TaskType::Z) => false,
+            _ => !self.y.is_empty() || !self.z.is_empty(),
+        }
+    }
+
+    fn on_preempt(&mut self, task: Task) {
+        match task.task_type {
+            Some(TaskType::Y) | Some(TaskType::Z) => self.admit(task),
+            _ => self.x.push_front(task),
+        }
+    }
+
+    fn tasks_mut(&mut self) -> Vec<&mut Task> {
+        self.x.iter_mut().chain(self.y.iter_mut()).chain(self.z.iter_mut()).collect()
+    }
+
+    fn queues(&self) -> Vec<(String, Vec<&Task>)> {
+        vec![
+            (String::from("Z QUEUE"), self.z.iter().collect()),
+            (String::from("Y QUEUE"), self.y.iter().collect()),
+            (String::from("X QUEUE"), self.x.iter().collect()),
+        ]
+    }
+
+    fn is_empty(&self) -> bool {
+        self.x.is_empty() && self.y.is_empty() && self.z.is_empty()
+    }
+}
+
+/// Multi-level feedback queue. Tasks enter at level 0; one that uses up its
+/// level's quantum is demoted a level, down to the last one which is plain
+/// round robin. A task only runs when every level above it is empty. Every
+/// `boost` clocks (0 disables it) all tasks go back to the top; tasks that
+/// are on a core or waiting when that happens are moved up the next time
+/// they pass through the scheduler.
+pub struct Mlfq {
+    levels: Vec<VecDeque<Task>>,
+    quanta: Vec<u16>,
+    boost: u32,
+    boosts: u32,
+    seen: HashMap<usize, u32>,
+}
+
+impl Mlfq {
+    pub fn new(quanta: Vec<u16>, boost: u32) -> Mlfq {
+        assert!(!quanta.is_empty() && quanta.iter().all(|q| *q > 0), "WRONG INPUT! every MLFQ level needs a quantum of at least 1");
+        Mlfq{levels: quanta.iter().map(|_| VecDeque::new()).collect(), quanta, boost, boosts: 0, seen: HashMap::new()}
+    }
+
+    fn enqueue(&mut self, mut task: Task) {
+        if self.seen.get(&task.id).is_some_and(|b| *b < self.boosts) {
+            task.level = 0;
+        }
+        self.seen.insert(task.id, self.boosts);
+        self.levels[task.level].push_back(task);
+    }
+}
+
+impl Scheduler for Mlfq {
+    fn admit(&mut self, task: Task) {
+        self.enqueue(task);
+    }
+
+    fn pick_next(&mut self) -> Option<Task> {
+        self.levels.iter_mut().find_map(|q| q.pop_front())
+    }
+
+    fn on_tick(&mut self, task: &mut Task, ran: u16) -> bool {
+        if ran >= self.quanta[task.level] {
+            task.level = (task.level + 1).min(self.levels.len() - 1);
+            return true;
+        }
+        false
+    }
+
+    fn should_preempt(&self, task: &Task) -> bool {
+        self.levels[..task.level].iter().any(|q| !q.is_empty())
+    }
+
+    fn on_clock(&mut self, clock: u32) {
+        if self.boost == 0 || !clock.is_multiple_of(self.boost) {
+            return;
+        }
+        self.boosts += 1;
+        let (top, rest) = self.levels.split_at_mut(1);
+        for q in rest {
+            for mut task in q.drain(..) {
+                task.level = 0;
+                top[0].push_back(task);
+            }
+        }
+        for task in top[0].iter() {
+            self.seen.insert(task.id, self.boosts);
+        }
+    }
+
+    fn tasks_mut(&mut self) -> Vec<&mut Task> {
+        self.levels.iter_mut().flat_map(|q| q.iter_mut()).collect()
+    }
+
+    fn queues(&self) -> Vec<(String, Vec<&Task>)> {
+        self.levels.iter().enumerate()
+            .map(|(l, q)| (format!("LEVEL {} QUEUE (quantum {})", l, self.quanta[l]), q.iter().collect()))
+            .collect()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.levels.iter().all(|q| q.is_empty())
+    }
+}
+
+/// xorshift64*, so lottery runs are reproducible from their seed without
+/// pulling in a dependency.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Rng {
+        Rng(seed.max(1))
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D) % n
+    }
+}
+
+/// Lottery scheduling: every dispatch draws a ticket from all ready tasks,
+/// so each one gets the CPU in proportion to its tickets on average. A
+/// winner holds its core for `quantum` clocks before the next draw.
+pub struct Lottery {
+    queue: VecDeque<Task>,
+    quantum: u16,
+    rng: Rng,
+}
+
+impl Lottery {
+    pub fn new(quantum: u16, seed: u64) -> Lottery {
+        assert!(quantum > 0, "WRONG INPUT! quantum must be at least 1");
+        Lottery{queue: VecDeque::new(), quantum, rng: Rng::new(seed)}
+    }
+}
+
+impl Scheduler for Lottery {
+    fn admit(&mut self, task: Task) {
+        self.queue.push_back(task);
+    }
+
+    fn pick_next(&mut self) -> Option<Task> {
+        let total: u64 = self.queue.iter().map(|t| t.tickets.max(1) as u64).sum();
+        if total == 0 {
+            return None;
+        }
+        let mut ticket = self.rng.below(total);
+        let mut winner = 0;
+        for (i, t) in self.queue.iter().enumerate() {
+            let tickets = t.tickets.max(1) as u64;
+            if ticket < tickets {
+                winner = i;
+                break;
+            }
+            ticket -= tickets;
+        }
+        self.queue.remove(winner)
+    }
+
+    fn on_tick(&mut self, _task: &mut Task, ran: u16) -> bool {
+        ran >= self.quantum
+    }
+
+    fn tasks_mut(&mut self) -> Vec<&mut Task> {
+        self.queue.iter_mut().collect()
+    }
+
+    fn queues(&self) -> Vec<(String, Vec<&Task>)> {
+        vec![(String::from("QUEUE"), self.queue.iter().collect())]
+    }
+
+    fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+}
+
+const STRIDE1: u64 = 1 << 20;
+
+/// Stride scheduling: the deterministic counterpart of `Lottery`. Each task
+/// advances its pass by `STRIDE1 / tickets` for every clock it runs and the
+/// lowest pass goes next, ties broken by input order. Newcomers start at
+/// the lowest pass in the queue so they can't monopolize the CPU.
+pub struct Stride {
+    queue: VecDeque<Task>,
+    quantum: u16,
+}
+
+impl Stride {
+    pub fn new(quantum: u16) -> Stride {
+        assert!(quantum > 0, "WRONG INPUT! quantum must be at least 1");
+        Stride{queue: VecDeque::new(), quantum}
+    }
+}
+
+impl Scheduler for Stride {
+    fn admit(&mut self, mut task: Task) {
+        let floor = self.queue.iter().map(|t| t.pass).min().unwrap_or(0);
+        task.pass = task.pass.max(floor);
+        self.queue.push_back(task);
+    }
+
+    fn pick_next(&mut self) -> Option<Task> {
+        let next = self.queue.iter().enumerate().min_by_key(|(_, t)| (t.pass, t.id)).map(|(i, _)| i)?;
+        self.queue.remove(next)
+    }
+
+    fn on_tick(&mut self, task: &mut Task, ran: u16) -> bool {
+        task.pass += STRIDE1 / task.tickets.max(1) as u64;
+        ran >= self.quantum
+    }
+
+    fn on_preempt(&mut self, task: Task) {
+        self.queue.push_back(task);
+    }
+
+    fn on_wakeup(&mut self, task: Task) {
+        self.queue.push_back(task);
+    }
+
+    fn tasks_mut(&mut self) -> Vec<&mut Task> {
+        self.queue.iter_mut().collect()
+    }
+
+    fn queues(&self) -> Vec<(String, Vec<&Task>)> {
+        vec![(String::from("QUEUE"), self.queue.iter().collect())]
+    }
+
+    fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+}
+
+/// The kernel's `sched_prio_to_weight`, indexed by nice + 20. Each step is
+/// roughly a 10% change in CPU share.
+const NICE_TO_WEIGHT: [u64; 40] = [
+    88761, 71755, 56483, 46273, 36291,
+    29154, 23254, 18705, 14949, 11916,
+    9548, 7620, 6100, 4904, 3906,
+    3121, 2501, 1991, 1586, 1277,
+    1024, 820, 655, 526, 423,
+    335, 272, 215, 172, 137,
+    110, 87, 70, 56, 45,
+    36, 29, 23, 18, 15,
+];
+
+const NICE_0_LOAD: u64 = 1024;
+
+fn weight(task: &Task) -> u64 {
+    NICE_TO_WEIGHT[(task.nice as i32 + 20) as usize]
+}
+
+/// Completely fair scheduling. Every clock a task runs adds
+/// `NICE_0_LOAD / weight` clocks (scaled by 1024) to its vruntime, and the
+/// ready task with the lowest vruntime runs next. A task's slice is its
+/// weight's share of the target latency, which stretches to
+/// `granularity` clocks per task once there are too many to fit.
+pub struct Cfs {
+    tree: BTreeMap<(u64, usize), Task>,
+    latency: u16,
+    granularity: u16,
+    min_vruntime: u64,
+}
+
+impl Cfs {
+    pub fn new(latency: u16, granularity: u16) -> Cfs {
+        assert!(granularity > 0, "WRONG INPUT! granularity must be at least 1");
+        Cfs{tree: BTreeMap::new(), latency, granularity, min_vruntime: 0}
+    }
+
+    fn slice(&self, task: &Task) -> u64 {
+        let running = self.tree.len() as u64 + 1;
+        let period = (self.latency as u64).max(running * self.granularity as u64);
+        let total: u64 = self.tree.values().map(weight).sum::<u64>() + weight(task);
+        (period * weight(task) / total).max(self.granularity as u64)
+    }
+}
+
+impl Scheduler for Cfs {
+    fn admit(&mut self, mut task: Task) {
+        task.vruntime = task.vruntime.max(self.min_vruntime);
+        self.tree.insert((task.vruntime, task.id), task);
+    }
+
+    fn pick_next(&mut self) -> Option<Task> {
+        let (_, task) = self.tree.pop_first()?;
+        self.min_vruntime = self.min_vruntime.max(task.vruntime);
+        Some(task)
+    }
+
+    fn on_tick(&mut self, task: &mut Task, ran: u16) -> bool {
+        task.vruntime += NICE_0_LOAD * NICE_0_LOAD / weight(task);
+        ran as u64 >= self.slice(task)
+    }
+
+    fn tasks_mut(&mut self) -> Vec<&mut Task> {
+        self.tree.values_mut().collect()
+    }
+
+    fn queues(&self) -> Vec<(String, Vec<&Task>)> {
+        vec![(String::from("QUEUE (by vruntime)"), self.tree.values().collect())]
+    }
+
+    fn is_empty(&self) -> bool {
+        self.tree.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn task(name: &str, task_type: TaskType, time: u16) -> Task {
+        Task::new(name.to_string(), Some(task_type), time)
+    }
+
+    /// Names in the order the policy hands its ready tasks out.
+    fn drain(sched: &mut dyn Scheduler) -> Vec<String> {
+        std::iter::from_fn(|| sched.pick_next()).map(|t| t.name).collect()
+    }
+
+    #[test]
+    fn fcfs_keeps_admission_order() {
+        let mut sched = Fcfs::default();
+        for (name, time) in [("A", 3), ("B", 1), ("C", 2)] {
+            sched.admit(task(name, TaskType::X, time));
+        }
+        assert_eq!(drain(&mut sched), vec!["A", "B", "C"]);
+    }
+
+    #[test]
+    fn sjf_runs_shortest_first() {
+        let mut sched = Sjf::default();
+        for (name, time) in [("A", 3), ("B", 1), ("C", 2)] {
+            sched.admit(task(name, TaskType::X, time));
+        }
+        assert_eq!(drain(&mut sched), vec!["B", "C", "A"]);
+    }
+
+    #[test]
+    fn mlq_serves_z_then_y_then_x() {
+        let mut sched = Mlq::default();
+        sched.admit(task("X1", TaskType::X, 1));
+        sched.admit(task("Y1", TaskType::Y, 1));
+        sched.admit(task("Z1", TaskType::Z, 1));
+        assert_eq!(drain(&mut sched), vec!["Z1", "Y1", "X1"]);
+    }
+
+    #[test]
+    fn mlq_puts_preempted_x_back_at_the_head() {
+        let mut sched = Mlq::default();
+        sched.admit(task("X1", TaskType::X, 3));
+        sched.admit(task("X2", TaskType::X, 3));
+        let mut running = sched.pick_next().unwrap();
+        assert!(!sched.on_tick(&mut running, 1));
+        assert!(!sched.should_preempt(&running));
+        sched.admit(task("Y1", TaskType::Y, 1));
+        assert!(sched.should_preempt(&running));
+        sched.on_preempt(running);
+        assert_eq!(drain(&mut sched), vec!["Y1", "X1", "X2"]);
+    }
+
+    #[test]
+    fn srtf_orders_by_time_left() {
+        let mut sched = Srtf::default();
+        let mut a = task("A", TaskType::X, 5);
+        a.time_executed = 4;
+        sched.admit(a);
+        sched.admit(task("B", TaskType::X, 2));
+        sched.admit(task("C", TaskType::X, 3));
+        assert_eq!(drain(&mut sched), vec!["A", "B", "C"]);
+    }
+
+    #[test]
+    fn srtf_preempts_for_shorter_arrival() {
+        let mut sched = Srtf::default();
+        let running = task("L", TaskType::X, 6);
+        assert!(!sched.should_preempt(&running));
+        sched.admit(task("M", TaskType::X, 6));
+        assert!(!sched.should_preempt(&running));
+        sched.admit(task("S", TaskType::X, 2));
+        assert!(sched.should_preempt(&running));
+    }
+
+    #[test]
+    fn round_robin_keeps_core_for_quantum() {
+        let mut sched = RoundRobin::new(3);
+        let mut running = task("A", TaskType::X, 5);
+        assert!(!sched.on_tick(&mut running, 2));
+        assert!(sched.on_tick(&mut running, 3));
+    }
+
+    #[test]
+    #[should_panic(expected = "quantum must be at least 1")]
+    fn round_robin_rejects_zero_quantum() {
+        RoundRobin::new(0);
+    }
+
+    fn prioritized(name: &str, id: usize, priority: u16) -> Task {
+        let mut task = task(name, TaskType::X, 2);
+        task.id = id;
+        task.priority = priority;
+        task
+    }
+
+    #[test]
+    fn priority_runs_most_important_first() {
+        let mut sched = Priority::new(false);
+        sched.admit(prioritized("A", 0, 2));
+        sched.admit(prioritized("B", 1, 1));
+        sched.admit(prioritized("C", 2, 2));
+        assert_eq!(drain(&mut sched), vec!["B", "A", "C"]);
+    }
+
+    #[test]
+    fn priority_preempts_only_when_asked() {
+        for preemptive in [false, true] {
+            let mut sched = Priority::new(preemptive);
+            let running = prioritized("A", 0, 2);
+            sched.admit(prioritized("B", 1, 2));
+            assert!(!sched.should_preempt(&running));
+            sched.admit(prioritized("C", 2, 1));
+            assert_eq!(sched.should_preempt(&running), preemptive);
+        }
+    }
+
+    /// Names on each MLFQ level, top first.
+    fn levels(sched: &Mlfq) -> Vec<Vec<String>> {
+        sched.queues().into_iter().map(|(_, q)| q.iter().map(|t| t.name.clone()).collect()).collect()
+    }
+
+    #[test]
+    fn mlfq_demotes_after_quantum() {
+        let mut sched = Mlfq::new(vec![1, 2], 0);
+        sched.admit(task("A", TaskType::X, 9));
+        let mut running = sched.pick_next().unwrap();
+        assert!(sched.on_tick(&mut running, 1));
+        assert_eq!(running.level, 1);
+        assert!(!sched.on_tick(&mut running, 1));
+        assert!(sched.on_tick(&mut running, 2));
+        assert_eq!(running.level, 1);
+        sched.on_preempt(running);
+        assert_eq!(levels(&sched), vec![Vec::<String>::new(), vec![String::from("A")]]);
+    }
+
+    #[test]
+    fn mlfq_higher_level_preempts() {
+        let mut sched = Mlfq::new(vec![1, 4], 0);
+        let mut running = task("A", TaskType::X, 9);
+        running.level = 1;
+        assert!(!sched.should_preempt(&running));
+        sched.admit(task("B", TaskType::X, 1));
+        assert!(sched.should_preempt(&running));
+        assert!(!sched.on_tick(&mut running, 1));
+    }
+
+    #[test]
+    fn mlfq_boost_lifts_everyone() {
+        let mut sched = Mlfq::new(vec![1, 1], 4);
+        for (id, name) in ["A", "B"].iter().enumerate() {
+            let mut t = task(name, TaskType::X, 9);
+            t.id = id;
+            t.level = 1;
+            sched.admit(t);
+        }
+        let running = sched.pick_next().unwrap();
+        sched.on_clock(3);
+        assert_eq!(levels(&sched)[1], vec!["B"]);
+        sched.on_clock(4);
+        assert_eq!(levels(&sched), vec![vec![String::from("B")], Vec::new()]);
+        sched.on_preempt(running);
+        assert_eq!(levels(&sched)[0], vec!["B", "A"]);
+    }
+
+    #[test]
+    fn mlfq_default_quanta_double_and_saturate() {
+        let sched = Algorithm::MLFQ.scheduler(&Options::parse("levels=18".split(' ')));
+        let names: Vec<String> = sched.queues().into_iter().map(|(name, _)| name).collect();
+        assert_eq!(names[0], "LEVEL 0 QUEUE (quantum 1)");
+        assert_eq!(names[15], "LEVEL 15 QUEUE (quantum 32768)");
+        assert_eq!(names[17], "LEVEL 17 QUEUE (quantum 65535)");
+    }
+
+    #[test]
+    #[should_panic(expected = "quantum of at least 1")]
+    fn mlfq_rejects_zero_quantum() {
+        Mlfq::new(vec![2, 0], 0);
+    }
+
+    #[test]
+    fn hrrn_picks_highest_response_ratio() {
+        let mut sched = Hrrn::default();
+        for (name, time, waited) in [("A", 8, 8), ("B", 2, 3), ("C", 4, 0), ("D", 2, 2)] {
+            let mut t = task(name, TaskType::X, time);
+            t.waiting_time = waited;
+            sched.admit(t);
+        }
+        // 2.5, then A and D tie at 2 and the earlier one goes first.
+        assert_eq!(drain(&mut sched), vec!["B", "A", "D", "C"]);
+    }
+
+    #[test]
+    fn hrrn_long_job_catches_up_by_waiting() {
+        let mut sched = Hrrn::default();
+        sched.admit(task("L", TaskType::X, 10));
+        sched.admit(task("S", TaskType::X, 1));
+        for t in sched.tasks_mut() {
+            t.waiting_time += 20;
+        }
+        // L: 30 / 10 = 3 against S: 21 / 1.
+        assert_eq!(drain(&mut sched), vec!["S", "L"]);
+        let mut sched = Hrrn::default();
+        let mut long = task("L", TaskType::X, 10);
+        long.waiting_time = 40;
+        sched.admit(long);
+        let mut short = task("S", TaskType::X, 1);
+        short.waiting_time = 3;
+        sched.admit(short);
+        assert_eq!(drain(&mut sched), vec!["L", "S"]);
+    }
+
+    fn ticketed(name: &str, id: usize, tickets: u32) -> Task {
+        let mut task = task(name, TaskType::X, 100);
+        task.id = id;
+        task.tickets = tickets;
+        task
+    }
+
+    /// Runs `clocks` one-clock dispatches and counts who got them.
+    fn shares(sched: &mut dyn Scheduler, clocks: usize) -> HashMap<String, usize> {
+        let mut runs = HashMap::new();
+        for _ in 0..clocks {
+            let mut running = sched.pick_next().unwrap();
+            *runs.entry(running.name.clone()).or_insert(0) += 1;
+            assert!(sched.on_tick(&mut running, 1));
+            sched.on_preempt(running);
+        }
+        runs
+    }
+
+    #[test]
+    fn lottery_is_proportional_and_seeded() {
+        let build = |seed| {
+            let mut sched = Lottery::new(1, seed);
+            sched.admit(ticketed("A", 0, 900));
+            sched.admit(ticketed("B", 1, 100));
+            sched
+        };
+        let runs = shares(&mut build(7), 1000);
+        assert!(runs["A"] > 800 && runs["B"] > 50, "{:?}", runs);
+        assert_eq!(runs, shares(&mut build(7), 1000));
+    }
+
+    #[test]
+    fn stride_splits_clocks_by_tickets() {
+        let mut sched = Stride::new(1);
+        sched.admit(ticketed("A", 0, 300));
+        sched.admit(ticketed("B", 1, 100));
+        let runs = shares(&mut sched, 8);
+        assert_eq!((runs["A"], runs["B"]), (6, 2));
+    }
+
+    #[test]
+    fn stride_newcomer_starts_at_lowest_pass() {
+        let mut sched = Stride::new(1);
+        let mut a = ticketed("A", 0, 100);
+        a.pass = 5000;
+        sched.admit(a);
+        sched.admit(ticketed("C", 1, 100));
+        assert!(sched.queues()[0].1.iter().all(|t| t.pass == 5000));
+    }
+
+    fn niced(name: &str, id: usize, nice: i8) -> Task {
+        let mut task = task(name, TaskType::X, 100);
+        task.id = id;
+        task.nice = nice;
+        task
+    }
+
+    /// The first clock `running` would be preempted after.
+    fn slice(sched: &mut Cfs, running: &mut Task) -> u16 {
+        (1..100).find(|ran| sched.on_tick(running, *ran)).unwrap()
+    }
+
+    #[test]
+    fn cfs_slice_is_weight_share_of_latency() {
+        let mut sched = Cfs::new(6, 1);
+        assert_eq!(slice(&mut sched, &mut niced("A", 0, 0)), 6);
+        sched.admit(niced("B", 1, 0));
+        assert_eq!(slice(&mut sched, &mut niced("A", 0, 0)), 3);
+        let mut sched = Cfs::new(6, 1);
+        sched.admit(niced("B", 1, 5));
+        // 6 * 1024 / (1024 + 335)
+        assert_eq!(slice(&mut sched, &mut niced("A", 0, 0)), 4);
+    }
+
+    #[test]
+    fn cfs_slice_stretches_to_granularity() {
+        let mut sched = Cfs::new(6, 2);
+        for id in 1..5 {
+            sched.admit(niced("B", id, 0));
+        }
+        assert_eq!(slice(&mut sched, &mut niced("A", 0, 0)), 2);
+    }
+
+    #[test]
+    fn cfs_vruntime_follows_weight() {
+        let mut sched = Cfs::new(6, 1);
+        let mut plain = niced("A", 0, 0);
+        let mut greedy = niced("B", 1, -5);
+        sched.on_tick(&mut plain, 1);
+        sched.on_tick(&mut greedy, 1);
+        assert_eq!((plain.vruntime, greedy.vruntime), (1024, 335));
+        sched.admit(plain);
+        sched.admit(greedy);
+        assert_eq!(drain(&mut sched), vec!["B", "A"]);
+    }
+
+    #[test]
+    fn cfs_newcomer_starts_at_min_vruntime() {
+        let mut sched = Cfs::new(6, 1);
+        let mut old = niced("A", 0, 0);
+        old.vruntime = 5000;
+        sched.admit(old);
+        sched.pick_next().unwrap();
+        sched.admit(niced("C", 1, 0));
+        assert_eq!(sched.pick_next().unwrap().vruntime, 5000);
+    }
+
+    fn due(name: &str, id: usize, deadline: Option<u32>) -> Task {
+        let mut task = task(name, TaskType::X, 2);
+        task.id = id;
+        task.deadline = deadline;
+        task
+    }
+
+    #[test]
+    fn edf_runs_earliest_deadline_first() {
+        let mut sched = Edf::default();
+        sched.admit(due("A", 0, None));
+        sched.admit(due("B", 1, Some(9)));
+        sched.admit(due("C", 2, Some(4)));
+        sched.admit(due("D", 3, Some(9)));
+        assert_eq!(drain(&mut sched), vec!["C", "B", "D", "A"]);
+    }
+
+    #[test]
+    fn edf_preempts_for_earlier_deadline() {
+        let mut sched = Edf::default();
+        let running = due("A", 0, Some(6));
+        sched.admit(due("B", 1, Some(6)));
+        assert!(!sched.should_preempt(&running));
+        sched.admit(due("C", 2, Some(5)));
+        assert!(sched.should_preempt(&running));
+    }
+
+    fn periodic(name: &str, id: usize, period: Option<u32>) -> Task {
+        let mut task = task(name, TaskType::X, 1);
+        task.id = id;
+        task.period = period;
+        task
+    }
+
+    #[test]
+    fn rm_runs_shortest_period_first() {
+        let mut sched = RateMonotonic::default();
+        sched.admit(periodic("A", 0, None));
+        sched.admit(periodic("B", 1, Some(10)).job(5, 1, 0));
+        sched.admit(periodic("C", 2, Some(4)).job(6, 3, 8));
+        assert_eq!(drain(&mut sched), vec!["C#3", "B#1", "A"]);
+    }
+
+    #[test]
+    fn rm_preempts_for_shorter_period() {
+        let mut sched = RateMonotonic::default();
+        let running = periodic("A", 0, Some(6));
+        sched.admit(periodic("B", 1, Some(6)));
+        assert!(!sched.should_preempt(&running));
+        sched.admit(periodic("C", 2, Some(3)));
+        assert!(sched.should_preempt(&running));
+    }
+
+    #[test]
+    fn jobs_are_due_a_period_after_release() {
+        let job = periodic("T", 0, Some(5)).job(7, 2, 10);
+        assert_eq!((job.id, job.name.as_str(), job.deadline), (7, "T#2", Some(15)));
+    }
+}
@@ -1,21 +1,30 @@
-use std::sync::{Arc, Mutex, MutexGuard, Barrier,Condvar};
+use std::sync::{Arc, Mutex, Barrier,Condvar};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::collections::VecDeque;
 use std::sync::mpsc::{Sender, Receiver};
 use std::fmt;
+use super::scheduler::Scheduler;
+use super::resources::*;
+use super::deadlock::Detector;
+use super::paging::Access;
+use super::devices::Device;
 
-#[derive(Debug, Copy, Clone)]
-pub enum Resource {
-    A,
-    B,
-    C
-}
-
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, Copy, Clone)]
 pub enum Algorithm{
     RR,
     SJF,
     FCFS,
-    MLQ
+    MLQ,
+    SRTF,
+    Priority { preemptive: bool },
+    MLFQ,
+    HRRN,
+    Lottery,
+    Stride,
+    CFS,
+    EDF,
+    RM
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -25,34 +34,235 @@ pub enum TaskType {
     Z
 }
 
+/// One step of a task's script.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Phase {
+    /// Run for this many more clocks.
+    Compute(u16),
+    /// Take these units on top of what the task holds, waiting if need be.
+    Acquire(Vec<u16>),
+    /// Give these units back.
+    Release(Vec<u16>),
+    /// Leave the core for an I/O burst of `time` clocks on a device, at
+    /// `track` for devices that seek.
+    Io { device: usize, time: u16, track: u32 },
+    /// Start a child from the task line of this name.
+    Fork(String),
+    /// Wait until every child started so far has left the system.
+    Join,
+}
+
+/// Where deadlock recovery rolls a task back to: the last time it held
+/// nothing beyond its initial demand, just before it asked for more.
+#[derive(Debug, Clone, Default)]
+pub struct Checkpoint {
+    pub time_executed: u16,
+    pub phases: VecDeque<Phase>,
+    pub resourses: Vec<u16>,
+}
+
 #[derive(Debug, Clone)]
 pub struct Task {
+    /// Position in the input, used to break ties in arrival order.
+    pub id : usize,
     pub name : String,
-    pub task_type : TaskType,
-    pub resourses : (Resource, Resource),
+    /// Optional label; picks the default demand and the MLQ level.
+    pub task_type : Option<TaskType>,
+    /// Units of each resource, by id, the task needs to run right now: its
+    /// initial demand plus whatever its script has acquired so far.
+    pub resourses : Vec<u16>,
+    /// Units of each resource the task holds right now.
+    pub held : Vec<u16>,
+    /// Most units of each resource the task may ever hold, for the banker's
+    /// algorithm; never less than `resourses` at any point of the script.
+    pub max_claim : Vec<u16>,
+    /// What is left of the task's script. Empty for tasks that just hold
+    /// `resourses` from start to finish.
+    pub phases : VecDeque<Phase>,
+    /// Units of contiguous memory the task holds from its first dispatch
+    /// until it finishes.
+    pub memory : u32,
+    /// Pages the task touches, one per clock of work, starting over at the
+    /// end.
+    pub pages : Vec<u32>,
+    /// Most frames the task keeps before replacing its own pages.
+    pub resident : usize,
+    pub faults : u32,
+    /// Clock at which a page the task faulted on is in.
+    pub blocked_until : u32,
     pub total_time : u16,
-    pub time_executed : u16
+    pub time_executed : u16,
+    pub checkpoint : Checkpoint,
+    /// Lower numbers run first. This is the priority the task runs at,
+    /// which a locking protocol may raise above `base_priority`.
+    pub priority : u16,
+    pub base_priority : u16,
+    /// Clocks spent blocked on resources held by a less important task.
+    pub inversion : u32,
+    /// Current MLFQ level, 0 being the top.
+    pub level : usize,
+    /// Clocks spent in the system without running, ready or blocked.
+    pub waiting_time : u32,
+    /// Last clock this task ran on a core.
+    pub last_ran : u32,
+    /// Share of the CPU under lottery and stride scheduling.
+    pub tickets : u32,
+    /// Stride scheduling pass value; the lowest pass runs next.
+    pub pass : u64,
+    /// CFS niceness, -20 (greediest) to 19.
+    pub nice : i8,
+    /// CFS weighted runtime; the lowest runs next.
+    pub vruntime : u64,
+    /// Absolute clock by which the task should have finished. On a periodic
+    /// template this is relative to each job's release instead.
+    pub deadline : Option<u32>,
+    /// Makes this a template that releases a job every `period` clocks.
+    pub period : Option<u32>,
+    /// Time of the first release for periodic tasks.
+    pub phase : u32,
+    /// Time the task enters the system; it can run from the next clock.
+    /// Tasks that come after others, periodic jobs and forked children get
+    /// the clock they actually entered.
+    pub arrival : u32,
+    /// Names of the tasks that have to finish before this one may start.
+    pub after : Vec<String>,
+    /// Id and name of the task that forked this one.
+    pub parent : Option<(usize, String)>,
+    /// Ids and names of the tasks this one forked.
+    pub children : Vec<(usize, String)>,
+    /// Clock in which the task ran its last unit of work.
+    pub finished_at : u32
+}
+
+/// Everything the cores and the master share, one lock per field. Locks are
+/// always taken in field order: scheduler, then resources, then waiting,
+/// then devices.
+#[derive(Clone)]
+pub struct Shared {
+    pub scheduler: Arc<Mutex<Box<dyn Scheduler>>>,
+    pub resourses: Arc<Mutex<ResourcePool>>,
+    pub waiting_queue: Arc<Mutex<VecDeque<Task>>>,
+    /// Tasks taken off a core this clock; the master hands them back to the
+    /// scheduler after the barrier so no other core runs them twice a clock.
+    pub preempted: Arc<Mutex<Vec<Task>>>,
+    pub finished: Arc<Mutex<Vec<Task>>>,
+    pub context_switches: Arc<AtomicUsize>,
+    pub devices: Arc<Mutex<Vec<Device>>>,
+    pub aborted: Arc<Mutex<Vec<Task>>>,
+    /// Task lines named in a fork, which only run as children.
+    pub templates: Arc<Vec<Task>>,
+    /// Children forked this clock; the master admits them after the
+    /// barrier, like `preempted`.
+    pub spawned: Arc<Mutex<Vec<Task>>>,
+    /// Id for the next task created during the run.
+    pub next_id: Arc<AtomicUsize>,
+}
+
+impl Shared {
+    /// Ids of the tasks that have left the system, finished or aborted.
+    pub fn exited(&self) -> Vec<usize> {
+        let finished = self.finished.lock().unwrap();
+        let aborted = self.aborted.lock().unwrap();
+        finished.iter().chain(aborted.iter()).map(|t| t.id).collect()
+    }
 }
 
 impl Task {
-    pub fn new(name: String, task_type: TaskType, time: u16) -> Task {
-        match task_type {
-            TaskType::X => {
-                return Task{name, task_type, resourses: (Resource::A, Resource::B), total_time: time, time_executed: 0};
-            },
-            TaskType::Y => {
-                return Task{name, task_type, resourses: (Resource::B, Resource::C), total_time: time, time_executed: 0};
-            },
-            TaskType::Z => {
-                return Task{name, task_type, resourses: (Resource::A, Resource::C), total_time: time, time_executed: 0};
+    /// A task of the given type needs one unit of two of the first three
+    /// resources in the pool (A, B and C by default); an unlabeled one
+    /// needs nothing until given its own demand.
+    pub fn new(name: String, task_type: Option<TaskType>, time: u16) -> Task {
+        let resourses = match task_type {
+            Some(TaskType::X) => vec![1, 1, 0],
+            Some(TaskType::Y) => vec![0, 1, 1],
+            Some(TaskType::Z) => vec![1, 0, 1],
+            None => Vec::new(),
+        };
+        Task{id: 0, name, task_type, max_claim: resourses.clone(), resourses, held: Vec::new(), phases: VecDeque::new(), memory: 0, pages: Vec::new(), resident: 0, faults: 0, blocked_until: 0, total_time: time, time_executed: 0, checkpoint: Checkpoint::default(), priority: 0, base_priority: 0, inversion: 0, level: 0, waiting_time: 0, last_ran: 0, tickets: 100, pass: 0, nice: 0, vruntime: 0, deadline: None, period: None, phase: 0, arrival: 0, after: Vec::new(), parent: None, children: Vec::new(), finished_at: 0}
+    }
+
+    /// The `k`th job of a periodic template, released at `release` with an
+    /// implicit deadline of one period unless the template gave its own.
+    pub fn job(&self, id: usize, k: u32, release: u32) -> Task {
+        let mut job = self.clone();
+        job.id = id;
+        job.name = format!("{}#{}", self.name, k);
+        job.deadline = Some(release + self.deadline.or(self.period).unwrap());
+        job.arrival = release;
+        job
+    }
+
+    /// What the task is waiting to be granted before it can run.
+    pub fn request(&self) -> Vec<u16> {
+        self.resourses.iter().enumerate().map(|(r, n)| n - self.held.get(r).copied().unwrap_or(0)).collect()
+    }
+
+    pub fn snapshot(&self) -> Checkpoint {
+        Checkpoint{time_executed: self.time_executed, phases: self.phases.clone(), resourses: self.resourses.clone()}
+    }
+
+    /// Goes back to the last checkpoint. The task must not hold anything.
+    /// Children forked since then stay forked, so their forks aren't redone.
+    pub fn rollback(&mut self) {
+        let forks = |phases: &VecDeque<Phase>| phases.iter().filter(|phase| matches!(phase, Phase::Fork(_))).count();
+        let mut done = forks(&self.checkpoint.phases) - forks(&self.phases);
+        self.time_executed = self.checkpoint.time_executed;
+        self.phases = self.checkpoint.phases.iter().filter(|phase| {
+            let skip = done > 0 && matches!(phase, Phase::Fork(_));
+            if skip {
+                done -= 1;
             }
-        } 
+            !skip
+        }).cloned().collect();
+        self.resourses = self.checkpoint.resourses.clone();
+    }
+
+    pub fn time_left(&self) -> u16 {
+        self.total_time - self.time_executed
+    }
+
+    /// Clocks the task's work and I/O take at the least.
+    pub fn length(&self) -> u32 {
+        let io: u32 = self.phases.iter().map(|phase| match phase {
+            Phase::Io{time, ..} => u32::from(*time),
+            _ => 0,
+        }).sum();
+        u32::from(self.time_left()) + io
+    }
+
+    /// Whether the task has no work, I/O, forks or joins left.
+    pub fn done(&self) -> bool {
+        self.time_left() == 0 && self.phases.iter().all(|phase| matches!(phase, Phase::Compute(_) | Phase::Acquire(_) | Phase::Release(_)))
+    }
+
+    /// Whether the task isn't held up joining children that are still in
+    /// the system.
+    pub fn joined(&self, exited: &[usize]) -> bool {
+        self.phases.front() != Some(&Phase::Join) || self.children.iter().all(|(c, _)| exited.contains(c))
+    }
+
+    /// A child of `parent` started from this task line at `clock`.
+    pub fn spawn(&self, id: usize, parent: &Task, clock: u32) -> Task {
+        let mut child = self.clone();
+        child.id = id;
+        child.arrival = clock;
+        child.name = format!("{}.{}", self.name, id);
+        child.parent = Some((parent.id, parent.name.clone()));
+        child
     }
 }
 
 impl fmt::Display for Task {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "\t{}: {{\n\t\ttotal time: {}\n\t\texecuted time: {}\n\t\ttime left: {}\n\t}}", self.name, self.total_time, self.time_executed, self.total_time - self.time_executed)
+        write!(f, "\t{}: {{\n\t\ttotal time: {}\n\t\texecuted time: {}\n\t\ttime left: {}\n\t\tpriority: {}\n\t\twaiting time: {}\n", self.name, self.total_time, self.time_executed, self.time_left(), self.priority, self.waiting_time)?;
+        if let Some((_, parent)) = &self.parent {
+            writeln!(f, "\t\tparent: {}", parent)?;
+        }
+        if !self.children.is_empty() {
+            let children: Vec<&str> = self.children.iter().map(|(_, c)| c.as_str()).collect();
+            writeln!(f, "\t\tchildren: {}", children.join(", "))?;
+        }
+        write!(f, "\t}}")
     }
 }
 
@@ -61,9 +271,6 @@ pub fn sleep_core(core_pair: Arc<(Mutex<bool>, Condvar)>) {
     let mut running = lock.lock().unwrap();
     while !*running {
         running = cond_var.wait(running).unwrap();
-        if *running == true {
-            break;
-        }
     }
     *running = false;
 }
@@ -75,496 +282,550 @@ pub fn wake_core(core_pair: Arc<(Mutex<bool>, Condvar)>) {
     cond_var.notify_all();
 }
 
-pub fn check_for_enough_resourse(ref mut have: &MutexGuard<((Resource, u16),(Resource, u16),(Resource, u16))>, need: (Resource, Resource)) -> bool {
-    match need {
-        (Resource::A, Resource::B) => {
-            if (*have).0.1 == 0 || (*have).1.1 == 0 {
-                return false;
-            }
-        },
-        (Resource::A, Resource::C) => {
-            if (*have).0.1 == 0 || (*have).2.1 == 0 {
-                return false;
-            }
-        },
-        (Resource::B, Resource::C) => {
-            if (*have).1.1 == 0 || (*have).2.1 == 0 {
-                return false;
-            }
+/// What the master releases into the scheduler as the run goes on.
+#[derive(Debug, Default)]
+pub struct Workload {
+    /// Tasks that haven't arrived yet or wait for the tasks they come
+    /// after, in input order.
+    pub pending: Vec<Task>,
+    pub periodic: Vec<Task>,
+    /// No periodic job is released at or after this time.
+    pub horizon: u32,
+    /// Longest chain of dependent tasks, with its length in clocks.
+    pub critical_path: Option<(u32, Vec<String>)>,
+}
+
+fn gcd(a: u32, b: u32) -> u32 {
+    if b == 0 { a } else { gcd(b, a % b) }
+}
+
+impl Workload {
+    /// By default periodic tasks run for one hyperperiod after the last
+    /// phase, which is enough to see every release pattern once.
+    pub fn default_horizon(periodic: &[Task]) -> u32 {
+        let hyperperiod = periodic.iter().filter_map(|t| t.period).fold(1, |l, p| l / gcd(l, p) * p);
+        let phase = periodic.iter().map(|t| t.phase).max();
+        phase.map_or(0, |phase| phase + hyperperiod)
+    }
+
+    /// Tasks that have arrived by `time` and only come after tasks in
+    /// `finished`, in input order.
+    fn arrivals(&mut self, time: u32, finished: &[Task]) -> Vec<Task> {
+        let ready = |t: &Task| t.arrival <= time && t.after.iter().all(|a| finished.iter().any(|f| f.name == *a));
+        let (mut arrived, pending): (Vec<Task>, Vec<Task>) = std::mem::take(&mut self.pending).into_iter().partition(ready);
+        self.pending = pending;
+        for task in arrived.iter_mut() {
+            task.arrival = time;
         }
-        _ => {}
+        arrived
     }
-    true
-}
 
-pub fn release_resources(have: &mut MutexGuard<((Resource, u16),(Resource, u16),(Resource, u16))>, need: &(Resource, Resource)) -> bool {
-    match need {
-        (Resource::A, Resource::B) => {
-            (*have).0.1 += 1;
-            (*have).1.1 += 1;
-        },
-        (Resource::A, Resource::C) => {
-            (*have).0.1 += 1;
-            (*have).2.1 += 1;
-        },
-        (Resource::B, Resource::C) => {
-            (*have).1.1 += 1;
-            (*have).2.1 += 1;
+    /// Takes out the tasks that come after an aborted one, directly or
+    /// through another task taken out.
+    fn cancel(&mut self, aborted: &[Task]) -> Vec<Task> {
+        let mut gone: Vec<String> = aborted.iter().map(|t| t.name.clone()).collect();
+        let mut cancelled = Vec::new();
+        loop {
+            let (out, pending): (Vec<Task>, Vec<Task>) = std::mem::take(&mut self.pending).into_iter().partition(|t| t.after.iter().any(|a| gone.contains(a)));
+            self.pending = pending;
+            if out.is_empty() {
+                return cancelled;
+            }
+            gone.extend(out.iter().map(|t| t.name.clone()));
+            cancelled.extend(out);
         }
-        _ => {}
     }
-    true
-}
 
-pub fn require_resources(have: &mut MutexGuard<((Resource, u16),(Resource, u16),(Resource, u16))>, need: &(Resource, Resource)) -> bool {
-    match need {
-        (Resource::A, Resource::B) => {
-            (*have).0.1 -= 1;
-            (*have).1.1 -= 1;
-        },
-        (Resource::A, Resource::C) => {
-            (*have).0.1 -= 1;
-            (*have).2.1 -= 1;
-        },
-        (Resource::B, Resource::C) => {
-            (*have).1.1 -= 1;
-            (*have).2.1 -= 1;
+    /// Jobs released at `time`, which the cores can run from the next clock.
+    fn release(&mut self, time: u32, next_id: &AtomicUsize) -> Vec<Task> {
+        let mut jobs = Vec::new();
+        if time >= self.horizon {
+            return jobs;
+        }
+        for template in self.periodic.iter() {
+            let period = template.period.unwrap();
+            if time >= template.phase && (time - template.phase).is_multiple_of(period) {
+                jobs.push(template.job(next_id.fetch_add(1, Ordering::SeqCst), (time - template.phase) / period, time));
+            }
         }
-        _ => {}
+        jobs
     }
-    true
 }
 
-pub fn cpu_worker(tx: Sender<String>, id: String, core_pair: Arc<(Mutex<bool>, Condvar)>, queue: Arc<Mutex<VecDeque<Task>>>, w_queue: Arc<Mutex<VecDeque<Task>>>, resourses: Arc<Mutex<((Resource, u16),(Resource, u16),(Resource, u16))>>, algo: Arc<Algorithm>, master: Arc<Barrier>, queues: Arc<Mutex<Vec<VecDeque<Task>>>>) {
-    match *algo {
-        Algorithm::FCFS => {
-            let mut on_proc = false;
-            let mut proc = None;
-            let mut idle_count = 0;
-            loop {
-                let ref mut proc = proc;
-                sleep_core(core_pair.clone());
-                if !on_proc {
-                    let lock  = &*(queue).clone();
-                    let mut q = lock.lock().unwrap();
-                    let temp = (*q).pop_front();
-                    *proc = temp;
-                    match proc {
-                        Some(p) => {
-                            let lock = &*(resourses);
-                            let mut r = lock.lock().unwrap();
-                            if !check_for_enough_resourse(&r, p.resourses) {
-                                let w_lock =  &*(w_queue).clone();
-                                let mut wq = w_lock.lock().unwrap();
-                                (*wq).push_back((*p).clone());
-                                *proc = None;
-                            } else {
-                                on_proc = true;
-                                require_resources(&mut r, &p.resourses);
-                            }
-                        },
-                        None => {}
-                    }
-                }
-                match proc {
-                    Some(p) => {
-                        p.time_executed = p.time_executed + 1;
-                        tx.send(std::format!("{} is processing:\n{}", id, p)).unwrap();
-                        if p.total_time - p.time_executed == 0 {
-                            {
-                                let lock = &*(resourses);
-                                let mut r = lock.lock().unwrap();
-                                release_resources(&mut r, &p.resourses);
-                            }
-                            *proc = None;
-                            on_proc = false;
+/// What a core does with a task it is about to run.
+enum Next {
+    Run,
+    /// Park it in the waiting queue.
+    Wait,
+    /// Hand it to the device of the I/O burst it starts.
+    Io(usize),
+    /// Take it out of the system; only joins or forks were left.
+    Done,
+}
+
+/// Works through the acquisitions and releases at the head of `p`'s script
+/// and grants it whatever it is short of, then pages in what it touches
+/// this clock. Says why in `log` if the task can't run.
+fn prepare(shared: &Shared, r: &mut ResourcePool, p: &mut Task, id: &str, clock: u32, log: &mut Vec<String>) -> Next {
+    while let Some(phase) = p.phases.front() {
+        match phase {
+            Phase::Compute(_) => break,
+            Phase::Io{device, ..} => return Next::Io(*device),
+            Phase::Fork(name) => {
+                let template = shared.templates.iter().find(|t| t.name == *name).unwrap();
+                let child = template.spawn(shared.next_id.fetch_add(1, Ordering::SeqCst), p, clock);
+                log.push(format!("{} forked {} from {}", id, child.name, p.name));
+                p.children.push((child.id, child.name.clone()));
+                shared.spawned.lock().unwrap().push(child);
+            },
+            Phase::Join => {
+                let exited = shared.exited();
+                if !p.joined(&exited) {
+                    let waiting: Vec<&str> = p.children.iter().filter(|(c, _)| !exited.contains(c)).map(|(_, c)| c.as_str()).collect();
+                    log.push(format!("{} parked {} joining {}", id, p.name, waiting.join(", ")));
+                    // Joining is a preemption like any other; under the
+                    // release policy the children may need what it holds,
+                    // its memory included.
+                    if r.preemption == Preemption::Release {
+                        let held = release_all(r, p);
+                        if held.iter().any(|h| *h > 0) {
+                            log.push(format!("{} released {} from {}", id, r.describe(&held), p.name));
+                        }
+                        if let Some(m) = r.memory.as_mut().filter(|m| m.address_of(p.id).is_some()) {
+                            m.free(p.id);
+                            log.push(format!("{} freed {} units of memory from {}", id, p.memory, p.name));
                         }
-                    },
-                    None => {
-                        idle_count += 1;
-                        on_proc = false;
-                        tx.send(std::format!("core {} idle, idle count: {}", id, idle_count)).unwrap();
                     }
+                    return Next::Wait;
                 }
-                master.wait();
-            } 
-        },
-        Algorithm::SJF => {
-            let mut on_proc = false;
-            let mut proc = None;
-            let mut idle_count = 0;
-            loop {
-                let ref mut proc = proc;
-                sleep_core(core_pair.clone());
-                if !on_proc {
-                    let lock  = &*(queue).clone();
-                    let mut q = lock.lock().unwrap();
-                    let temp = (*q).pop_front();
-                    *proc = temp;
-                    match proc {
-                        Some(p) => {
-                            let lock = &*(resourses);
-                            let mut r = lock.lock().unwrap();
-                            if !check_for_enough_resourse(&r, p.resourses) {
-                                let w_lock =  &*(w_queue).clone();
-                                let mut wq = w_lock.lock().unwrap();
-                                (*wq).push_back((*p).clone());
-                                (*wq).make_contiguous().sort_by(|a, b| a.total_time.cmp(&b.total_time));
-                                *proc = None;
-                            } else {
-                                on_proc = true;
-                                require_resources(&mut r, &p.resourses);
-                            }
-                        },
-                        None => {}
-                    }
+            },
+            Phase::Acquire(units) => {
+                if p.resourses == p.checkpoint.resourses {
+                    p.checkpoint = p.snapshot();
                 }
-                match proc {
-                    Some(p) => {
-                        p.time_executed = p.time_executed + 1;
-                        tx.send(std::format!("{} is processing:\n{}", id, p)).unwrap();
-                        if p.total_time - p.time_executed == 0 {
-                            let lock = &*(resourses);
-                            let mut r = lock.lock().unwrap();
-                            release_resources(&mut r, &p.resourses); 
-                            *proc = None;
-                            on_proc = false;
-                        }
-                    },
-                    None => {
-                        on_proc = false;
-                        idle_count += 1;
-                        tx.send(std::format!("core {} idle, idle count: {}", id, idle_count)).unwrap();
+                let units = units.clone();
+                p.resourses.resize(p.resourses.len().max(units.len()), 0);
+                for (n, u) in p.resourses.iter_mut().zip(units.iter()) {
+                    *n += *u;
+                }
+            },
+            Phase::Release(units) => {
+                // A task preempted under the release policy no longer holds
+                // what its script is about to give back.
+                let mut units = units.clone();
+                for (r, u) in units.iter_mut().enumerate() {
+                    if *u > 0 {
+                        p.resourses[r] -= *u;
+                        *u = (*u).min(p.held.get(r).copied().unwrap_or(0));
                     }
+                    if *u > 0 {
+                        p.held[r] -= *u;
+                    }
+                }
+                if units.iter().any(|u| *u > 0) {
+                    release_resources(r, p.id, &units);
+                    log.push(format!("{} released {} from {}", id, r.describe(&units), p.name));
                 }
-                master.wait();
-            } 
+            },
+        }
+        p.phases.pop_front();
+    }
+    if p.time_left() == 0 {
+        return Next::Done;
+    }
+    if !r.allocate_memory(p) {
+        log.push(format!("{} found no hole of {} for {}", id, p.memory, p.name));
+        return Next::Wait;
+    }
+    let request = p.request();
+    if request.iter().any(|n| *n > 0) {
+        if !r.can_grant(p.id, &request) {
+            log.push(format!("{} blocked {} waiting for {}", id, p.name, r.describe(&request)));
+            return Next::Wait;
+        }
+        if r.banker {
+            let sequence = r.safe_sequence_after(p.id, &request).unwrap();
+            log.push(format!("{} granted {} to {}, safe sequence: <{}>", id, r.describe(&request), p.name, sequence.join(", ")));
+        } else if p.time_executed > 0 {
+            log.push(format!("{} granted {} to {}", id, r.describe(&request), p.name));
+        }
+        acquire(r, p, &request);
+    }
+    if let (false, Some(frames)) = (p.pages.is_empty(), &mut r.paging) {
+        if let Access::Fault(evicted) = frames.access(p) {
+            p.faults += 1;
+            p.blocked_until = clock + frames.latency;
+            let page = p.pages[p.time_executed as usize % p.pages.len()];
+            let evicted = evicted.map_or(String::new(), |(owner, page)| format!(", evicting page {} of {}", page, owner));
+            log.push(format!("{} page fault: {} waits for page {} until clock {}{}", id, p.name, page, p.blocked_until, evicted));
+            return Next::Wait;
+        }
+    }
+    Next::Run
+}
+
+/// Moves a task that can't run on from the core: to the waiting queue, or
+/// to the device it does I/O on.
+fn park(shared: &Shared, sched: &mut dyn Scheduler, p: Task, next: Next, id: &str, log: &mut Vec<String>) {
+    let mut wq = shared.waiting_queue.lock().unwrap();
+    match next {
+        Next::Io(device) => {
+            let mut devices = shared.devices.lock().unwrap();
+            log.push(format!("{} sent {} to {}", id, p.name, devices[device].name));
+            devices[device].submit(p);
         },
-        Algorithm::RR => {
-            let mut proc = None;
-            let mut idle_count = 0;
-            loop {
-                let ref mut proc = proc;
-                sleep_core(core_pair.clone());
-                loop {
-                    let lock  = &*(queue).clone();
-                    let mut q = lock.lock().unwrap();
-                    let lock = &*(resourses);
-                    let r = lock.lock().unwrap();
-                    let temp = (*q).pop_front();
-                    *proc = temp;
-                    match proc {
-                        Some(p) => {
-                            if !check_for_enough_resourse(&r, p.resourses) {
-                                let w_lock =  &*(w_queue).clone();
-                                let mut wq = w_lock.lock().unwrap();
-                                (*wq).push_back((*p).clone());
-                            } else {
-                                break;
-                            }
-                        },
-                        None => {
-                            break;
-                        }
-                    }
+        _ => sched.on_block(p, &mut wq),
+    }
+}
+
+/// Takes a task with nothing left to do out of the system.
+fn finish(sched: &mut dyn Scheduler, r: &mut ResourcePool, finished: &Mutex<Vec<Task>>, mut p: Task, clock: u32) {
+    release_all(r, &mut p);
+    r.retire(p.id);
+    p.finished_at = clock;
+    sched.on_complete(&p);
+    finished.lock().unwrap().push(p);
+}
+
+/// Pulls tasks from the scheduler until one can run, parking the ones that
+/// can't get their resources or start I/O.
+fn dispatch(shared: &Shared, id: &str, clock: u32, log: &mut Vec<String>) -> Option<Task> {
+    let mut sched = shared.scheduler.lock().unwrap();
+    let mut r = shared.resourses.lock().unwrap();
+    while let Some(mut p) = sched.pick_next() {
+        match prepare(shared, &mut r, &mut p, id, clock, log) {
+            Next::Run => return Some(p),
+            Next::Done => finish(&mut **sched, &mut r, &shared.finished, p, clock),
+            next => park(shared, &mut **sched, p, next, id, log),
+        }
+    }
+    None
+}
+
+pub fn cpu_worker(tx: Sender<String>, id: String, core_pair: Arc<(Mutex<bool>, Condvar)>, shared: Shared, master: Arc<Barrier>) {
+    let mut proc: Option<Task> = None;
+    let mut ran = 0;
+    let mut idle_count = 0;
+    let mut clock: u32 = 0;
+    loop {
+        sleep_core(core_pair.clone());
+        clock += 1;
+        let mut log = Vec::new();
+        if let Some(mut p) = proc.take() {
+            let mut sched = shared.scheduler.lock().unwrap();
+            let mut r = shared.resourses.lock().unwrap();
+            if let Some(priority) = r.effective_priority(p.id) {
+                p.priority = priority;
+            }
+            if sched.should_preempt(&p) {
+                if r.preemption == Preemption::Release {
+                    release_all(&mut r, &mut p);
                 }
-                match proc {
-                    Some(p) => {
-                        let lock  = &*(queue).clone();
-                        let mut q = lock.lock().unwrap();
-                        p.time_executed = p.time_executed + 1;
-                        if p.total_time - p.time_executed > 0 {
-                            (*q).push_back((*p).clone());
-                        }
-                        tx.send(std::format!("{} is processing:\n{}", id, p)).unwrap();
+                shared.context_switches.fetch_add(1, Ordering::SeqCst);
+                sched.on_preempt(p);
+            } else {
+                match prepare(&shared, &mut r, &mut p, &id, clock, &mut log) {
+                    Next::Run => proc = Some(p),
+                    Next::Done => finish(&mut **sched, &mut r, &shared.finished, p, clock),
+                    next => {
+                        shared.context_switches.fetch_add(1, Ordering::SeqCst);
+                        park(&shared, &mut **sched, p, next, &id, &mut log);
                     },
-                    None => {
-                        idle_count += 1;
-                        tx.send(std::format!("core {} idle, idle count: {}", id, idle_count)).unwrap();
-                    }
-                }   
-                master.wait();
-            }
-        },
-        Algorithm::MLQ => {
-            let mut idle_count = 0;
-            let mut on_proc = false;
-            let mut proc : Option<Task>= None;
-
-            loop {
-                sleep_core(core_pair.clone());
-                let lock  = &*(queues).clone();
-                let mut qs = lock.lock().unwrap();
-                if qs[2].len() > 0 {
-                    match proc {
-                        Some(ref mut p) => {
-                            qs[0].push_front(p.clone());
-                            proc = None;
-                        },
-                        _ => ()
-                    }
-                    let ref mut q = qs[2];
-                    let ref mut proc = proc;
-                    loop {
-                        let lock = &*(resourses);
-                        let r = lock.lock().unwrap();
-                        let temp = q.pop_front();
-                        *proc = temp;
-                        match proc {
-                            Some(p) => {
-                                if !check_for_enough_resourse(&r, p.resourses) {
-                                    let w_lock =  &*(w_queue).clone();
-                                    let mut wq = w_lock.lock().unwrap();
-                                    (*wq).push_back((*p).clone());
-                                } else {
-                                    break;
-                                }
-                            },
-                            None => {
-                                break;
-                            }
-                        }
-                    }
-                    match proc {
-                        Some(p) => {
-                            p.time_executed = p.time_executed + 1;
-                            if p.total_time - p.time_executed > 0 {
-                                q.push_back((*p).clone());
-                            }
-                            tx.send(std::format!("{} is processing:\n{}", id, p)).unwrap();
-                        },
-                        None => {
-                            idle_count += 1;
-                            tx.send(std::format!("core {} idle, idle count: {}", id, idle_count)).unwrap();
-                        }
-                    }   
-                    *proc = None;
                 }
-                else if qs[1].len() > 0 {
-                    match proc {
-                        Some(ref mut p) => {
-                            qs[0].push_front(p.clone());
-                            proc = None;
-                        },
-                        _ => ()
-                    }
-                    let ref mut q = qs[1];
-                    let ref mut proc = proc;
-                    loop {
-                        let lock = &*(resourses);
-                        let r = lock.lock().unwrap();
-                        let temp = q.pop_front();
-                        *proc = temp;
-                        match proc {
-                            Some(p) => {
-                                if !check_for_enough_resourse(&r, p.resourses) {
-                                    let w_lock =  &*(w_queue).clone();
-                                    let mut wq = w_lock.lock().unwrap();
-                                    (*wq).push_back((*p).clone());
-                                } else {
-                                    break;
-                                }
-                            },
-                            None => {
-                                break;
-                            }
-                        }
+            }
+        }
+        if proc.is_none() {
+            proc = dispatch(&shared, &id, clock, &mut log);
+            ran = 0;
+        }
+        let log = log.iter().map(|l| format!("{}\n", l)).collect::<String>();
+        match proc.take() {
+            Some(mut p) => {
+                p.time_executed += 1;
+                if let Some(Phase::Compute(n)) = p.phases.front_mut() {
+                    *n -= 1;
+                    if *n == 0 {
+                        p.phases.pop_front();
                     }
-                    match proc {
-                        Some(p) => {
-                            p.time_executed = p.time_executed + 1;
-                            if p.total_time - p.time_executed > 0 {
-                                q.push_back((*p).clone());
-                            }
-                            tx.send(std::format!("{} is processing:\n{}", id, p)).unwrap();
-                        },
-                        None => {
-                            idle_count += 1;
-                            tx.send(std::format!("core {} idle, idle count: {}", id, idle_count)).unwrap();
-                        }
-                    }  
-                    *proc = None;
                 }
-                else {
-                    let ref mut q = qs[0]; 
-                    let ref mut proc = proc;
-                    if !on_proc {
-                        let temp = (*q).pop_front();
-                        *proc = temp;
-                        match proc {
-                            Some(p) => {
-                                let lock = &*(resourses);
-                                let mut r = lock.lock().unwrap();
-                                if !check_for_enough_resourse(&r, p.resourses) {
-                                    let w_lock =  &*(w_queue).clone();
-                                    let mut wq = w_lock.lock().unwrap();
-                                    (*wq).push_back((*p).clone());
-                                    *proc = None;
-                                } else {
-                                    on_proc = true;
-                                    require_resources(&mut r, &p.resourses);
-                                }
-                            },
-                            None => {}
-                        }
-                    }
-                    match proc {
-                        Some(p) => {
-                            p.time_executed = p.time_executed + 1;
-                            tx.send(std::format!("{} is processing:\n{}", id, p)).unwrap();
-                            if p.total_time - p.time_executed == 0 {
-                                {
-                                    let lock = &*(resourses);
-                                    let mut r = lock.lock().unwrap();
-                                    release_resources(&mut r, &p.resourses);
-                                }
-                                *proc = None;
-                                on_proc = false;
-                            }
-                        },
-                        None => {
-                            idle_count += 1;
-                            on_proc = false;
-                            tx.send(std::format!("core {} idle, idle count: {}", id, idle_count)).unwrap();
-                        }
+                p.last_ran = clock;
+                ran += 1;
+                tx.send(std::format!("{}{} is processing:\n{}", log, id, p)).unwrap();
+                let done = p.done();
+                let mut sched = shared.scheduler.lock().unwrap();
+                if done {
+                    let mut r = shared.resourses.lock().unwrap();
+                    finish(&mut **sched, &mut r, &shared.finished, p, clock);
+                } else if sched.on_tick(&mut p, ran) {
+                    let mut r = shared.resourses.lock().unwrap();
+                    if r.preemption == Preemption::Release {
+                        release_all(&mut r, &mut p);
                     }
+                    drop(r);
+                    shared.context_switches.fetch_add(1, Ordering::SeqCst);
+                    shared.preempted.lock().unwrap().push(p);
+                } else {
+                    proc = Some(p);
                 }
-                drop(lock);
-                drop(qs);
-                master.wait();
+            },
+            None => {
+                idle_count += 1;
+                tx.send(std::format!("{}core {} idle, idle count: {}", log, id, idle_count)).unwrap();
             }
         }
+        master.wait();
+    }
+}
+
+fn print_queues(sched: &dyn Scheduler) {
+    for (name, tasks) in sched.queues() {
+        println!("{}: [", name);
+        for t in tasks {
+            println!("{}", t);
+        }
+        println!("]");
+    }
+}
+
+/// Prints `task` and everything it forked, one level of indentation per
+/// generation.
+fn print_tree(finished: &[Task], aborted: &[Task], task: &Task, depth: usize) {
+    let indent = "\t".repeat(depth);
+    if finished.iter().any(|t| t.id == task.id) {
+        println!("{}{}: finished at {}", indent, task.name, task.finished_at);
+    } else {
+        println!("{}{}: aborted", indent, task.name);
+    }
+    for (c, _) in task.children.iter() {
+        if let Some(child) = finished.iter().chain(aborted.iter()).find(|t| t.id == *c) {
+            print_tree(finished, aborted, child, depth + 1);
+        }
     }
 }
 
-pub fn master_worker(rx: Receiver<String>, cores: Vec<Arc<(Mutex<bool>, Condvar)>>, resourses: Arc<Mutex<((Resource, u16),(Resource, u16),(Resource, u16))>>, queue: Arc<Mutex<VecDeque<Task>>>, w_queue: Arc<Mutex<VecDeque<Task>>>, algo: Arc<Algorithm>, master: Arc<Barrier>, queues: Arc<Mutex<Vec<VecDeque<Task>>>>) {
-     {
-        let lock  = &*(queue).clone();
-        let q = lock.lock().unwrap();
-        println!("QUEUE: [");
-        for i in 0..(*q).len() {
-            println!("{}", &(*q)[i]);
+/// End of run summary for whatever the run asked to be judged on.
+fn report(finished: &[Task], aborted: &[Task]) {
+    if finished.iter().chain(aborted.iter()).any(|t| t.parent.is_some()) {
+        println!("PROCESS TREE: [");
+        let mut roots: Vec<&Task> = finished.iter().chain(aborted.iter()).filter(|t| t.parent.is_none() && !t.children.is_empty()).collect();
+        roots.sort_by_key(|t| t.id);
+        for t in roots {
+            print_tree(finished, aborted, t, 1);
+        }
+        println!("]");
+    }
+    if finished.iter().any(|t| t.inversion > 0) {
+        println!("PRIORITY INVERSION: [");
+        let mut inverted: Vec<&Task> = finished.iter().filter(|t| t.inversion > 0).collect();
+        inverted.sort_by_key(|t| t.id);
+        for t in inverted {
+            println!("\t{}: blocked by lower priority tasks for {} clocks", t.name, t.inversion);
+        }
+        println!("]");
+    }
+    if !aborted.is_empty() {
+        println!("ABORTED: [");
+        for t in aborted {
+            println!("{}", t);
         }
         println!("]");
     }
-    let mut clocks = 0;
+    let mut finished: Vec<&Task> = finished.iter().collect();
+    finished.sort_by_key(|t| t.id);
+    if finished.iter().any(|t| !t.pages.is_empty()) {
+        println!("PAGING: [");
+        for t in finished.iter().filter(|t| !t.pages.is_empty()) {
+            println!("\t{}: {} faults in {} references with {} frames", t.name, t.faults, t.total_time, t.resident);
+        }
+        println!("]");
+    }
+    if finished.iter().any(|t| t.deadline.is_some()) {
+        println!("DEADLINE MISSES: [");
+        for t in finished.iter() {
+            if let Some(deadline) = t.deadline {
+                if t.finished_at > deadline {
+                    println!("\t{}: deadline {}, finished at {}, late by {}", t.name, deadline, t.finished_at, t.finished_at - deadline);
+                }
+            }
+        }
+        println!("]");
+    }
+}
+
+pub fn master_worker(rx: Receiver<String>, cores: Vec<Arc<(Mutex<bool>, Condvar)>>, shared: Shared, mut workload: Workload, detector: Detector, master: Arc<Barrier>) {
+    print_queues(&**shared.scheduler.lock().unwrap());
+    let mut clocks: u32 = 0;
     loop {
         clocks += 1;
-        let rx = &rx;
-        for i in 0..4 {
-            wake_core(cores[i].clone());
+        {
+            let mut sched = shared.scheduler.lock().unwrap();
+            let mut r = shared.resourses.lock().unwrap();
+            let mut aborted = shared.aborted.lock().unwrap();
+            for task in workload.cancel(&aborted) {
+                println!("cancelled {}: it comes after an aborted task", task.name);
+                aborted.push(task);
+            }
+            drop(aborted);
+            for task in workload.arrivals(clocks - 1, &shared.finished.lock().unwrap()) {
+                if task.after.is_empty() {
+                    println!("arrived {}", task.name);
+                } else {
+                    println!("{} may start: {} done", task.name, task.after.join(", "));
+                }
+                r.register(&task);
+                sched.admit(task);
+            }
+            for job in workload.release(clocks - 1, &shared.next_id) {
+                println!("released {}", job.name);
+                r.register(&job);
+                sched.admit(job);
+            }
+            sched.on_clock(clocks);
+        }
+        for core in cores.iter() {
+            wake_core(core.clone());
         }
         master.wait();
-        match *algo {
-            Algorithm::MLQ => { 
-                let lock  = &*(queues).clone();
-                let mut qs = lock.lock().unwrap();
-                let r_lock = &*(resourses);
-                let r = r_lock.lock().unwrap();
-                println!("<<at {} clock>>", clocks);
-                println!("Resources : {:?}", r); 
-                println!("Z QUEUE: [");
-                for i in 0..(qs.get_mut(2).unwrap()).len() {
-                    println!("{}", &(qs.get_mut(2).unwrap())[i]);
-                }
-                println!("]");
-                println!("Y QUEUE: [");
-                for i in 0..(qs.get_mut(1).unwrap()).len() {
-                    println!("{}", &(qs.get_mut(1).unwrap())[i]);
-                }
-                println!("]");
-                println!("X QUEUE: [");
-                for i in 0..(qs.get_mut(0).unwrap()).len() {
-                    println!("{}", &(qs.get_mut(0).unwrap())[i]);
-                }
-                println!("]");
-                let mut idle_count = 0;
-                for _ in 0..4 {
-                    let msg = rx.recv().unwrap();
-                    if msg.contains("idle") {
-                        idle_count += 1;
-                    }
-                    println!("{}", msg);
+        let mut sched = shared.scheduler.lock().unwrap();
+        let mut preempted = shared.preempted.lock().unwrap();
+        preempted.sort_by_key(|t| t.id);
+        for p in preempted.drain(..) {
+            sched.on_preempt(p);
+        }
+        drop(preempted);
+        let mut r = shared.resourses.lock().unwrap();
+        for child in shared.spawned.lock().unwrap().drain(..) {
+            r.register(&child);
+            sched.admit(child);
+        }
+        println!("<<at {} clock>>", clocks);
+        println!("Resources : {}", *r);
+        if let Some(m) = &mut r.memory {
+            println!("Memory : {}", m);
+            m.sample();
+        }
+        if let Some(frames) = &r.paging {
+            println!("Frames : {}", frames);
+        }
+        print_queues(&**sched);
+        let mut idle_count = 0;
+        for _ in 0..cores.len() {
+            let msg = rx.recv().unwrap();
+            if msg.contains("idle") {
+                idle_count += 1;
+            }
+            println!("{}", msg);
+        }
+        let mut wq = shared.waiting_queue.lock().unwrap();
+        for t in sched.tasks_mut().into_iter().chain(wq.iter_mut()) {
+            if t.last_ran != clocks {
+                t.waiting_time += 1;
+            }
+        }
+        let mut devices = shared.devices.lock().unwrap();
+        for device in devices.iter_mut() {
+            if let Some(p) = device.tick() {
+                println!("{} done with {}", device.name, p.name);
+                if p.done() {
+                    finish(&mut **sched, &mut r, &shared.finished, p, clocks);
+                } else {
+                    sched.on_wakeup(p);
                 }
-                let w_lock =  &*(w_queue).clone();
-                let mut wq = w_lock.lock().unwrap();
-                let temp = (*wq).pop_front();
-                match temp {
-                    Some(p) => {
-                        if check_for_enough_resourse(&r, p.resourses) {
-                            match p.task_type {
-                                TaskType::X => {
-                                    (qs.get_mut(0).unwrap()).push_back(p);
-                                },
-                                TaskType::Y => {
-                                    (qs.get_mut(1).unwrap()).push_back(p);
-                                },
-                                TaskType::Z => {
-                                    (qs.get_mut(2).unwrap()).push_back(p);
-                                }
-                            }
-
-                        } else {
-                            (*wq).push_back(p);
-                        }
-                    },
-                    None => {}
+            }
+            println!("{}", device);
+        }
+        for (p, grant) in r.wake(&mut wq, clocks, &shared.exited()) {
+            if let Some(grant) = grant {
+                println!("{}", grant);
+            }
+            println!("woke {}", p.name);
+            sched.on_wakeup(p);
+        }
+        if detector.period > 0 && clocks.is_multiple_of(detector.period) {
+            detector.run(&mut r, &mut wq, &mut shared.aborted.lock().unwrap());
+        }
+        for t in wq.iter_mut() {
+            if r.inverted(t) {
+                t.inversion += 1;
+            }
+        }
+        if r.protocol != Protocol::None {
+            r.update_priorities(&wq);
+            for t in sched.tasks_mut().into_iter().chain(wq.iter_mut()) {
+                if let Some(priority) = r.effective_priority(t.id) {
+                    t.priority = priority;
                 }
-                if idle_count == 4 && (qs.get_mut(0).unwrap()).len() == 0 && (qs.get_mut(1).unwrap()).len() == 0 && (qs.get_mut(2).unwrap()).len() == 0 && (*wq).len() == 0 {
-                    println!("TOTAL CLOCKS:  {}", clocks);
-                    return;
-                } 
-            },
-            _ => {
-                let lock  = &*(queue).clone();
-                let mut q = lock.lock().unwrap();
-                let r_lock = &*(resourses);
-                let r = r_lock.lock().unwrap();
-                println!("<<at {} clock>>", clocks);
-                println!("Resources : {:?}", r); 
-                println!("QUEUE: [");
-                for i in 0..(*q).len() {
-                    println!("{}", &(*q)[i]);
+            }
+            sched.reorder();
+        }
+        if idle_count == cores.len() && sched.is_empty() && wq.is_empty() && devices.iter().all(|d| d.is_empty()) && workload.pending.is_empty() && clocks >= workload.horizon {
+            println!("TOTAL CLOCKS:  {}", clocks);
+            println!("CONTEXT SWITCHES:  {}", shared.context_switches.load(Ordering::SeqCst));
+            if let Some(m) = &r.memory {
+                println!("MEMORY: {} allocations, {} waits for a hole, peak external fragmentation {:.0}%, peak internal fragmentation {} units", m.allocations, m.failures, m.peak_external * 100.0, m.peak_internal);
+            }
+            if let Some(frames) = &r.paging {
+                println!("PAGE FAULTS: {}", frames.faults);
+            }
+            if !devices.is_empty() {
+                println!("DEVICES: [");
+                for d in devices.iter() {
+                    println!("\t{}: busy {} of {} clocks, {} requests served, head moved {} tracks", d.name, d.busy, clocks, d.served, d.moved);
                 }
                 println!("]");
-                let mut idle_count = 0;
-                for _ in 0..4 {
-                    let msg = rx.recv().unwrap();
-                    if msg.contains("idle") {
-                        idle_count += 1;
-                    }
-                    println!("{}", msg);
-                }
-                let w_lock =  &*(w_queue).clone();
-                let mut wq = w_lock.lock().unwrap();
-                let temp = (*wq).pop_front();
-                match temp {
-                    Some(p) => {
-                        if check_for_enough_resourse(&r, p.resourses) {
-                            (*q).push_back(p);
-                            match *algo {
-                                Algorithm::SJF => {
-                                    (*q).make_contiguous().sort_by(|a, b| a.total_time.cmp(&b.total_time)); 
-                                },
-                                _ => {}
-                            }
-                        } else {
-                            (*wq).push_back(p);
-                            match *algo {
-                                Algorithm::SJF => {
-                                    (*wq).make_contiguous().sort_by(|a, b| a.total_time.cmp(&b.total_time)); 
-                                },
-                                _ => {}
-                            }
-                        }
-                    },
-                    None => {}
-                }
-                if idle_count == 4 && (*q).len() == 0 && (*wq).len() == 0 {
-                    println!("TOTAL CLOCKS:  {}", clocks);
-                    return;
-                }
             }
+            if !r.balanced() {
+                println!("RESOURCES NOT BALANCED: {}", *r);
+            }
+            report(&shared.finished.lock().unwrap(), &shared.aborted.lock().unwrap());
+            if let Some((length, path)) = &workload.critical_path {
+                let makespan = shared.finished.lock().unwrap().iter().map(|t| t.finished_at).max().unwrap_or(0);
+                println!("CRITICAL PATH: {} ({} clocks), MAKESPAN: {} clocks on {} cores", path.join(" -> "), length, makespan, cores.len());
             }
+            return;
         }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::scheduler::Fcfs;
+    use super::super::memory::{MemoryPool, Strategy};
+
+    fn shared(resourses: ResourcePool) -> Shared {
+        Shared {
+            scheduler: Arc::new(Mutex::new(Box::new(Fcfs::default()))),
+            resourses: Arc::new(Mutex::new(resourses)),
+            waiting_queue: Arc::new(Mutex::new(VecDeque::new())),
+            preempted: Arc::new(Mutex::new(Vec::new())),
+            finished: Arc::new(Mutex::new(Vec::new())),
+            context_switches: Arc::new(AtomicUsize::new(0)),
+            devices: Arc::new(Mutex::new(Vec::new())),
+            aborted: Arc::new(Mutex::new(Vec::new())),
+            templates: Arc::new(Vec::new()),
+            spawned: Arc::new(Mutex::new(Vec::new())),
+            next_id: Arc::new(AtomicUsize::new(2)),
+        }
+    }
+
+    #[test]
+    fn joining_frees_memory_for_the_child() {
+        let mut pool = ResourcePool::new(Vec::new());
+        pool.memory = Some(MemoryPool::new(8, Strategy::FirstFit));
+        let shared = shared(pool);
+        let mut parent = Task::new(String::from("P"), None, 1);
+        parent.memory = 6;
+        parent.phases = VecDeque::from(vec![Phase::Join, Phase::Compute(1)]);
+        parent.children.push((1, String::from("C")));
+        let mut child = Task::new(String::from("C"), None, 1);
+        child.id = 1;
+        child.memory = 4;
+        let mut r = shared.resourses.lock().unwrap();
+        assert!(r.allocate_memory(&parent));
+        let mut log = Vec::new();
+        assert!(matches!(prepare(&shared, &mut r, &mut parent, "core0", 0, &mut log), Next::Wait));
+        assert!(r.allocate_memory(&child));
+        assert_eq!(log.last().unwrap(), "core0 freed 6 units of memory from P");
+    }
 }